@@ -2,7 +2,7 @@ use core::fmt;
 use std::{fmt::Display, fs::File, io::Write};
 
 use crate::{
-    input::{HostInput, Joyp}, memory::{self, Memory, MemoryType, Mmu, LCDC}, ppu::Ppu, PpuStatus
+    input::{HostInput, Joyp}, memory::{self, mbc::RomLoadError, Memory, MemoryType, Mmu, LCDC}, ppu::Ppu, Model, PpuStatus
 };
 
 use self::instructions::{
@@ -10,15 +10,30 @@ use self::instructions::{
     WordArithmeticTarget,
 };
 
+pub use self::assembler::{assemble, AsmError};
+pub use self::call_stack::{CallStack, Frame, StackMismatch};
 pub use self::instructions::Instruction;
 pub use self::registers::{CpuReg, CpuFlag, Flags, Registers};
+pub use self::condition::{BinOp, Expr, ExprContext, ExprError};
+pub use self::disassembler::{disassemble, disassemble_range, Disassembly};
+pub use self::interrupts::Interrupt;
+pub use self::trace::{Trace, TraceFormat};
+pub use self::watchpoints::{Access, WatchHit, Watchpoint};
 
 
+mod assembler;
+mod call_stack;
+mod condition;
+mod disassembler;
 mod instructions;
+mod interrupts;
 mod registers;
+mod trace;
+mod watchpoints;
 
 const EXT_PREFIX: u8 = 0xCB;
-const STAT_INT: u16 = 0x0048;
+/// M-cycles the CPU is paused for while the clock settles after a speed switch
+const SPEED_SWITCH_CYCLES: usize = 2050;
 
 #[derive(Clone, Copy, Debug)]
 pub struct IoRegs {
@@ -39,9 +54,18 @@ pub enum CpuEvent {
     Pc(u16),
     MemoryRead(u16),
     MemoryWrite(u16),
-    Interrupt(u8),
+    /// An interrupt's bit was set in IF
+    InterruptRequest(Interrupt),
+    /// An interrupt was serviced, and PC is now at its handler
+    Interrupt(Interrupt),
     Flag(CpuFlag),
     Reg(CpuReg),
+    /// Only used as a breakpoint, hit when `CpuReg` changes to a value that satisfies the comparison
+    RegValue(CpuReg, Comparison),
+    /// Conditional breakpoint `id` was hit, see `Breakpoints::set_condition()`
+    Condition(usize),
+    /// A watchpoint was triggered, only pushed for accesses that match one
+    Watch(WatchHit),
     LdBb,
 }
 
@@ -61,7 +85,8 @@ impl PartialEq for CpuEvent {
             | (MemoryWrite(lhs), MemoryWrite(rhs)) => {
                 lhs == rhs
             },
-            (Interrupt(lhs), Interrupt(rhs)) => {
+            (InterruptRequest(lhs), InterruptRequest(rhs))
+            | (Interrupt(lhs), Interrupt(rhs)) => {
                 lhs == rhs
             },
             (Flag(lhs), Flag(rhs)) => {
@@ -70,6 +95,15 @@ impl PartialEq for CpuEvent {
             (Reg(lhs), Reg(rhs)) => {
                 lhs == rhs
             },
+            (RegValue(lhs, lhs_cmp), RegValue(rhs, rhs_cmp)) => {
+                lhs == rhs && lhs_cmp == rhs_cmp
+            },
+            (Condition(lhs), Condition(rhs)) => {
+                lhs == rhs
+            },
+            (Watch(lhs), Watch(rhs)) => {
+                lhs == rhs
+            },
             (LdBb, LdBb) => true,
             (_, _) => false,
         }
@@ -84,9 +118,12 @@ pub struct EnabledBreakpoints {
     pub pc: bool,
     pub memory_read: bool,
     pub memory_write: bool,
+    pub interrupt_request: bool,
     pub interrupt: bool,
     pub flag_change: bool,
     pub reg_change: bool,
+    pub condition: bool,
+    pub watch: bool,
     pub ld_b_b: bool,
 }
 
@@ -99,9 +136,12 @@ impl EnabledBreakpoints {
             pc: true,
             memory_read: true,
             memory_write: true,
+            interrupt_request: true,
             interrupt: true,
             flag_change: true,
             reg_change: true,
+            condition: true,
+            watch: true,
             ld_b_b: true,
         }
     }
@@ -115,42 +155,96 @@ impl EnabledBreakpoints {
             Pc(_) => self.pc,
             MemoryRead(_) => self.memory_read,
             MemoryWrite(_) => self.memory_write,
+            InterruptRequest(_) => self.interrupt_request,
             Interrupt(_) => self.interrupt,
             Flag(_) => self.flag_change,
-            Reg(_) => self.reg_change,
+            Reg(_) | RegValue(_, _) => self.reg_change,
+            Condition(_) => self.condition,
+            Watch(_) => self.watch,
             LdBb => self.ld_b_b,
         }
     }
 }
 
+/// A test against a register value, used by `CpuEvent::RegValue` breakpoints
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Eq(u16),
+    Ne(u16),
+    Lt(u16),
+    Le(u16),
+    Gt(u16),
+    Ge(u16),
+}
+
+impl Comparison {
+    /// Returns whether `value` satisfies the comparison
+    pub fn matches(&self, value: u16) -> bool {
+        match *self {
+            Comparison::Eq(rhs) => value == rhs,
+            Comparison::Ne(rhs) => value != rhs,
+            Comparison::Lt(rhs) => value < rhs,
+            Comparison::Le(rhs) => value <= rhs,
+            Comparison::Gt(rhs) => value > rhs,
+            Comparison::Ge(rhs) => value >= rhs,
+        }
+    }
+}
+
+/// A breakpoint that is hit when its condition is true
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConditionalBreakpoint {
+    pub id: usize,
+    pub condition: Expr,
+    /// Only check the condition when this event happens, otherwise it's checked after every instruction
+    pub event: Option<CpuEvent>,
+    /// Number of times the condition has been checked, available to the condition as `hits`
+    pub hits: u32,
+}
+
 #[derive(Debug)]
 pub struct Breakpoints {
     pub breakpoints: Vec<CpuEvent>,
+    pub conditions: Vec<ConditionalBreakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
     pub enabled_kinds: EnabledBreakpoints,
     pub master_enable: bool,
+    next_condition_id: usize,
+    next_watchpoint_id: usize,
 }
 
 impl Breakpoints {
     fn new() -> Self {
         Self {
             breakpoints: Vec::new(),
+            conditions: Vec::new(),
+            watchpoints: Vec::new(),
             enabled_kinds: EnabledBreakpoints::new(),
             master_enable: true,
+            next_condition_id: 0,
+            next_watchpoint_id: 0,
         }
     }
     
     /// This is used to check if an internal event matches any active breakpoints
     /// 
-    /// If it does match, the breakpoint is passed back out to be forwarded to the frontend
-    fn check(&self, value: CpuEvent) -> Option<CpuEvent> {
+    /// If it does match, the breakpoint is passed back out to be forwarded to the frontend.
+    /// `regs` is used to test `CpuEvent::RegValue` breakpoints against register change events
+    fn check(&self, value: CpuEvent, regs: &Registers) -> Option<CpuEvent> {
         if !self.master_enable || !self.enabled_kinds.is_enabled(value) {
             None
         } else {
-            if self.breakpoints.iter().any(|bp| &value == bp) {
-                Some(value)
-            } else {
-                None
+            // watch events are only pushed when a watchpoint matched
+            if let CpuEvent::Watch(_) = value {
+                return Some(value);
             }
+
+            self.breakpoints.iter().find_map(|&bp| match (bp, value) {
+                (CpuEvent::RegValue(reg, cmp), CpuEvent::Reg(changed)) => {
+                    (reg == changed && cmp.matches(regs.get(reg))).then_some(bp)
+                }
+                _ => (bp == value).then_some(value),
+            })
         }
     }
 
@@ -158,6 +252,43 @@ impl Breakpoints {
         self.breakpoints.push(breakpoint);
     }
 
+    /// Adds a breakpoint that is hit when `condition` is true, returning its id
+    ///
+    /// With an `event`, the condition is only checked when that event happens, otherwise it's checked after every
+    /// instruction. Hitting it breaks with `CpuEvent::Condition(id)`
+    ///
+    /// ### Return Variants
+    /// - `Err(ExprError)` if `condition` couldn't be parsed
+    pub fn set_condition(&mut self, condition: &str, event: Option<CpuEvent>) -> Result<usize, ExprError> {
+        let condition = Expr::parse(condition)?;
+        let id = self.next_condition_id;
+        self.next_condition_id += 1;
+
+        self.conditions.push(ConditionalBreakpoint { id, condition, event, hits: 0 });
+        Ok(id)
+    }
+
+    /// Adds a watchpoint, returning its id
+    ///
+    /// Hitting it breaks with `CpuEvent::Watch`, which reports the id and the details of the access
+    pub fn set_watchpoint(&mut self, mut watchpoint: Watchpoint) -> usize {
+        watchpoint.id = self.next_watchpoint_id;
+        self.next_watchpoint_id += 1;
+
+        self.watchpoints.push(watchpoint);
+        self.next_watchpoint_id - 1
+    }
+
+    /// Removes the watchpoint with the id `id`
+    pub fn unset_watchpoint(&mut self, id: usize) {
+        self.watchpoints.retain(|wp| wp.id != id);
+    }
+
+    /// Removes the conditional breakpoint with the id `id`
+    pub fn unset_condition(&mut self, id: usize) {
+        self.conditions.retain(|bp| bp.id != id);
+    }
+
     pub fn unset(&mut self, breakpoint: CpuEvent) {
         self.breakpoints = self.breakpoints.iter().filter_map(
             |&b| {
@@ -178,6 +309,8 @@ pub enum CpuStatus {
     Stop,
     Halt,
     BlockedByDma,
+    /// An illegal opcode was executed and the CPU has hard locked, only a reset will recover it
+    Locked,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -191,11 +324,29 @@ pub struct Cpu<T: Memory> {
     pub regs: Registers,
     pub memory: Box<T>,
     pub ppu: Ppu,
+    /// Whether the CGB is running in double speed mode, where the CPU and timers run twice as fast as the PPU
     pub double_speed: bool,
+    /// KEY1 bit 0, switches speed on the next STOP
+    pub speed_switch_armed: bool,
+    /// Alternates every M-cycle in double speed, the PPU only ticks when it's set
+    ppu_phase: bool,
     pub halted: bool,
+    /// Set when an illegal opcode is executed, the CPU stops fetching instructions but the rest of the system keeps
+    /// running
+    pub locked: bool,
+    /// Set when HALT is executed with IME clear and an interrupt pending, the next opcode byte is then read twice
+    pub halt_bug: bool,
+    /// The interrupt dispatched during the last step, if any
+    pub serviced: Option<Interrupt>,
     pub debug: bool,
     pub allow_uninit: bool,
     pub breakpoint_controls: Breakpoints,
+    /// Shadow of the routines that were called and haven't returned, for backtraces
+    pub call_stack: CallStack,
+    /// Writes the state before every instruction when set
+    pub trace: Option<Trace>,
+    /// Hardware model being emulated, picks the post boot state and gates model specific behaviour
+    pub model: Model,
     pub host_input: HostInput,
     pub joyp: Joyp,
     ei_called: u8,
@@ -212,7 +363,8 @@ pub struct Cpu<T: Memory> {
 }
 
 impl<T: Memory> Cpu<T> {
-    pub fn new(memory: T, ppu: Ppu, debug: bool, allow_uninit: bool) -> Self {
+    /// Creates a CPU in the state `model`'s boot ROM leaves it in
+    pub fn new(memory: T, ppu: Ppu, model: Model, debug: bool, allow_uninit: bool) -> Self {
         let log = if debug {
             Some(File::create("gb.log").unwrap())
         } else {
@@ -220,18 +372,26 @@ impl<T: Memory> Cpu<T> {
         };
 
         Self {
-            regs: Registers::new(),
+            regs: Registers::post_boot(model),
             memory: Box::new(memory),
             ppu,
             double_speed: false,
+            speed_switch_armed: false,
+            ppu_phase: false,
             halted: false,
+            locked: false,
+            halt_bug: false,
+            serviced: None,
             debug,
             allow_uninit,
             breakpoint_controls: Breakpoints::new(),
+            call_stack: CallStack::new(),
+            trace: None,
+            model,
             host_input: HostInput::new(),
             joyp: Joyp::new(),
             ei_called: 0,
-            div: 0,
+            div: model.post_boot_div(),
             div_and: false,
             tima_overflow: false,
             stop: false,
@@ -242,23 +402,45 @@ impl<T: Memory> Cpu<T> {
         }
     }
 
+    /// Resets the CPU and PPU to their power on state, for running a boot ROM
+    ///
+    /// Memory is left alone, the caller is expected to have reset it
+    pub(crate) fn power_on(&mut self) {
+        self.regs = Registers::power_on();
+        self.ppu = Ppu::power_on(self.model);
+        self.double_speed = false;
+        self.speed_switch_armed = false;
+        self.ppu_phase = false;
+        self.halted = false;
+        self.locked = false;
+        self.halt_bug = false;
+        self.stop = false;
+        self.ei_called = 0;
+        self.div = 0;
+        self.div_and = false;
+        self.tima_overflow = false;
+        self.tick = 0;
+        self.dma = None;
+        self.pending_breakpoints.clear();
+        self.call_stack.clear();
+    }
+
     pub(crate) fn load_rom(&mut self, data: &[u8]) {
         self.memory.load_rom(data);
     }
 
+    pub(crate) fn try_load_rom(&mut self, data: &[u8]) -> Result<(), RomLoadError> {
+        self.memory.try_load_rom(data)
+    }
+
     /// Ticks the system by 1 M-cycle, stepping the PPU and DIV
     pub(crate) fn tick(&mut self) {
+        // in double speed an M-cycle only takes 2 PPU dots
+        self.tick += if self.double_speed { 2 } else { 4 };
+
         // there is a single tick delay between TIMA overflowing and IF.2 being set
-        self.tick += 4;
         if self.tima_overflow {
-            let mut if_reg = self
-                .memory
-                .load(memory::IF)
-                .expect("Error reading IF register: Uninitialized");
-
-            if_reg |= 1 << 2;
-
-            self.memory.set(memory::IF, if_reg);
+            self.request_interrupt(Interrupt::Timer);
             self.tima_overflow = false;
         }
 
@@ -273,23 +455,45 @@ impl<T: Memory> Cpu<T> {
             }
         }
 
-        if self.ppu.enabled {
-            self.ppu.tick(&mut *self.memory);
-        }
-
-        if self.ppu.status == PpuStatus::EnterVBlank {
-            let mut if_reg = self
-                .memory
-                .load(memory::IF)
-                .expect("Error reading IF register: Uninitialized");
+        // the PPU runs at the same rate in either speed, so it only gets every other cycle in double speed
+        self.ppu_phase = !self.ppu_phase;
+        if !self.double_speed || self.ppu_phase {
+            if self.ppu.enabled {
+                // the PPU sets the STAT bit in IF on its own
+                let before = self.memory.load(memory::IF).unwrap_or(0);
+                self.ppu.tick(&mut *self.memory);
+                let after = self.memory.load(memory::IF).unwrap_or(0);
+                self.push_interrupt_requests(before, after);
+            }
 
-            if_reg |= 1 << 0;
-            self.memory.set(memory::IF, if_reg);
+            if self.ppu.status == PpuStatus::EnterVBlank {
+                self.request_interrupt(Interrupt::VBlank);
+            }
         }
 
         self.tick_div();
     }
 
+    /// Sets `interrupt`'s bit in IF
+    pub(crate) fn request_interrupt(&mut self, interrupt: Interrupt) {
+        let if_reg = self
+            .memory
+            .load(memory::IF)
+            .expect("Error reading IF register: Uninitialized");
+
+        self.memory.set(memory::IF, if_reg | interrupt.mask());
+        self.push_interrupt_requests(if_reg, if_reg | interrupt.mask());
+    }
+
+    /// Pushes an `InterruptRequest` for every bit that went from 0 to 1 when IF changed from `old` to `new`
+    fn push_interrupt_requests(&mut self, old: u8, new: u8) {
+        for interrupt in Interrupt::ALL {
+            if new & !old & interrupt.mask() > 0 {
+                self.push_event(CpuEvent::InterruptRequest(interrupt));
+            }
+        }
+    }
+
     fn tick_div(&mut self) {
         // div increases every T-cycle
         self.div = self.div.wrapping_add(4);
@@ -333,11 +537,32 @@ impl<T: Memory> Cpu<T> {
     /// Executes a CPU instruction and moves the PC to its next position.
     ///
     /// ### Return Variants
-    /// - `Ok(true)` if operation should continue
-    /// - `Ok(false)` if STOP was called and execution should stop
-    /// - `Err(addr)` if there was an attempt to read from uninitialized memory
+    /// - `Ok(CpuStatus::Run(instruction))` if `instruction` was executed
+    /// - `Ok(CpuStatus::Break(instruction, event))` if executing `instruction` hit the breakpoint `event`
+    /// - `Ok(CpuStatus::Halt)` if the CPU is halted, or just woke up from HALT
+    /// - `Ok(CpuStatus::Stop)` if STOP was executed, or the system is still waiting in STOP mode for a joypad press
+    /// - `Ok(CpuStatus::Locked)` if the CPU has locked up after an illegal opcode
+    /// - `Err(CpuError::MemoryLoadFail(addr))` if there was an attempt to read from uninitialized memory
+    /// - `Err(CpuError::IllegalOpcode)` if the opcode at PC doesn't exist, which locks the CPU
     pub(crate) fn step(&mut self) -> Result<CpuStatus, CpuError> {
         self.dbg("Loading instruction\n");
+        self.serviced = None;
+
+        if self.locked {
+            // interrupts can't wake the CPU, but the PPU and timers keep going
+            self.tick();
+            self.pending_breakpoints.clear();
+            return Ok(CpuStatus::Locked);
+        }
+
+        if self.stop {
+            // any selected joypad line going low brings the system out of STOP, until then nothing runs
+            if self.joyp.serialize(self.host_input) & 0x0F == 0x0F {
+                return Ok(CpuStatus::Stop);
+            }
+
+            self.stop = false;
+        }
 
         if self.halted {
             let Some(ie) = self
@@ -352,13 +577,23 @@ impl<T: Memory> Cpu<T> {
                     return Err(CpuError::MemoryLoadFail(memory::IF));
                 };
 
-            if ie & if_reg > 0 {
+            // a cycle passes either way
+            self.tick();
+
+            if ie & if_reg & 0x1F > 0 {
+                // waking up takes another cycle, before the interrupt is dispatched with IME set,
+                // and before execution continues after the HALT with IME clear
+                let old_regs = self.regs;
                 self.halted = false;
+                self.tick();
                 self.handle_interrupts();
+                self.diff_regs(old_regs);
             }
 
-            self.tick();
-            return Ok(CpuStatus::Halt);
+            return match self.take_breakpoint() {
+                Some(breakpoint) => Ok(CpuStatus::Break(Instruction::HALT, breakpoint)),
+                None => Ok(CpuStatus::Halt),
+            };
         }
 
         // if self.oam_dma_running() && self.regs.pc < memory::HRAM {
@@ -367,7 +602,18 @@ impl<T: Memory> Cpu<T> {
         //     return Ok(CpuStatus::BlockedByDma)
         // }
 
-        let instruction_byte = self.mem_load(self.regs.pc)?;
+        self.write_trace();
+
+        let old_regs = self.regs;
+        let instruction_byte = self.fetch(self.regs.pc)?;
+
+        // the halt bug stops PC from incrementing past the opcode, so moving PC back has the
+        // byte read again as the next part of the instruction
+        if self.halt_bug {
+            self.halt_bug = false;
+            self.regs.pc = self.regs.pc.wrapping_sub(1);
+        }
+
         let (instruction_byte, prefixed) = if instruction_byte == EXT_PREFIX {
             (self.load_d8()?, true)
         } else {
@@ -381,10 +627,11 @@ impl<T: Memory> Cpu<T> {
         }
 
         let Some(instruction) = Instruction::from_byte(prefixed, instruction_byte) else {
-            panic!(
-                "Undefined opcode at {:#06X} ({instruction_byte:#04X})",
-                self.regs.pc
-            );
+            self.locked = true;
+            return Err(CpuError::IllegalOpcode {
+                pc: self.regs.pc,
+                opcode: instruction_byte,
+            });
         };
 
         self.push_event(CpuEvent::Instruction(instruction));
@@ -394,8 +641,6 @@ impl<T: Memory> Cpu<T> {
         self.push_event(CpuEvent::Pc(self.regs.pc));
 
         if self.stop {
-            self.tick();
-            self.tick();
             return Ok(CpuStatus::Stop);
         }
 
@@ -408,18 +653,50 @@ impl<T: Memory> Cpu<T> {
         }
 
         self.handle_interrupts();
+        self.diff_regs(old_regs);
 
-        let breakpoints = self.pending_breakpoints.clone();
-        self.pending_breakpoints = Vec::with_capacity(8);
-
-        if let Some(breakpoint) = breakpoints.iter().find_map(|&b| self.breakpoint_controls.check(b)) {
+        if let Some(breakpoint) = self.take_breakpoint() {
             Ok(CpuStatus::Break(instruction, breakpoint))
         } else {
             Ok(CpuStatus::Run(instruction))
         }
     }
 
-    // TODO: clean this up (enum probably)
+    /// Clears the events collected during a step, returning the first one that matches a breakpoint
+    ///
+    /// Conditional breakpoints are checked after the plain ones
+    fn take_breakpoint(&mut self) -> Option<CpuEvent> {
+        let breakpoints = std::mem::replace(&mut self.pending_breakpoints, Vec::with_capacity(8));
+        let hit = breakpoints.iter().find_map(|&b| self.breakpoint_controls.check(b, &self.regs));
+
+        let controls = &self.breakpoint_controls;
+        if !controls.master_enable || !controls.enabled_kinds.condition || controls.conditions.is_empty() {
+            return hit;
+        }
+
+        // taken out so the conditions can look at the rest of the CPU
+        let mut conditions = std::mem::take(&mut self.breakpoint_controls.conditions);
+        let mut condition_hit = None;
+
+        for bp in conditions.iter_mut() {
+            let checks = match bp.event {
+                Some(event) => breakpoints.iter().filter(|&&e| e == event).count(),
+                None => 1,
+            };
+
+            for _ in 0..checks {
+                bp.hits += 1;
+                if condition_hit.is_none() && bp.condition.is_true(&*self, bp.hits) {
+                    condition_hit = Some(CpuEvent::Condition(bp.id));
+                }
+            }
+        }
+
+        self.breakpoint_controls.conditions = conditions;
+        hit.or(condition_hit)
+    }
+
+    /// Services the highest priority interrupt that is both requested and enabled, if IME is set
     fn handle_interrupts(&mut self) {
         if self.regs.ime {
             let ie = self
@@ -439,27 +716,42 @@ impl<T: Memory> Cpu<T> {
                 return;
             }
 
-            for i in 0..5 {
-                let ie_bit = ie & (1 << i);
-
-                if ie_bit > 0 && ie_bit == if_reg & (1 << i) {
-                    // TODO: use an enum and push events
-                    // self.push_event(CpuEvent::Interrupt(i));
-
-                    // acknowledge the interrupt and prevent further interrupts
-                    self.mem_set(memory::IF, if_reg - (1 << i));
+            for interrupt in Interrupt::ALL {
+                if ie & if_reg & interrupt.mask() > 0 {
+                    // acknowledge the interrupt and prevent further interrupts, this happens during the
+                    // dispatch itself rather than taking a cycle of its own
+                    self.memory.set(memory::IF, if_reg & !interrupt.mask());
                     self.regs.ime = false;
+                    self.halted = false;
+
+                    // if HALT was bugged by `ei; halt`, the interrupt returns to the HALT instead
+                    if self.halt_bug {
+                        self.halt_bug = false;
+                        self.regs.pc = self.regs.pc.wrapping_sub(1);
+                    }
 
                     // 2 wait cycles are executed
                     self.tick();
                     self.tick();
 
                     // pc is pushed to the stack
-                    self.push_word(self.regs.pc);
+                    let call_site = self.regs.pc;
+                    let return_sp = self.regs.sp;
+                    self.push_word(call_site);
 
                     // the 16 bit ISR address is loaded into pc, taking another cycle
-                    self.regs.pc = 0x40 + 0x08 * i as u16;
+                    self.regs.pc = interrupt.vector();
                     self.tick();
+                    self.serviced = Some(interrupt);
+                    self.call_stack.enter(call_site, Frame {
+                        call_site,
+                        target: self.regs.pc,
+                        bank: self.memory.rom_bank(),
+                        interrupt: Some(interrupt),
+                        return_addr: call_site,
+                        return_sp,
+                    });
+                    self.push_event(CpuEvent::Interrupt(interrupt));
 
                     return;
                 }
@@ -474,7 +766,6 @@ impl<T: Memory> Cpu<T> {
         }
 
         let mut size = 1;
-        let old_regs = self.regs;
 
         match instruction {
             Instruction::ADD(target)
@@ -705,9 +996,22 @@ impl<T: Memory> Cpu<T> {
             }
             Instruction::DAA => self.regs.a = self.daa(),
             Instruction::STOP => {
-                self.stop = true;
+                // the byte after STOP is skipped
+                size = 2;
+                self.enter_stop();
+            }
+            Instruction::HALT => {
+                let ie = self.memory.load(memory::IE).unwrap_or(0);
+                let if_reg = self.memory.load(memory::IF).unwrap_or(0);
+
+                // with IME clear and an interrupt already pending, HALT exits immediately and the next opcode
+                // byte gets read twice
+                if !self.regs.ime && ie & if_reg & 0x1F > 0 {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
             }
-            Instruction::HALT => self.halted = true,
             Instruction::NOP => {}
             Instruction::RET(test) => return self.ret(test),
             Instruction::RETI => return self.reti(),
@@ -734,44 +1038,59 @@ impl<T: Memory> Cpu<T> {
             _ => {}
         }
 
-        self.diff_regs(old_regs);
-
         Ok(self.regs.pc.wrapping_add(size))
     }
 
-    /// Push events for any changed registers
-    fn diff_regs(&mut self, old_regs: Registers) {
-        if self.regs.a != old_regs.a {
-            self.push_event(CpuEvent::Reg(CpuReg::A));
+    /// Either switches speed if it was armed through KEY1 on CGB, or enters STOP mode until a joypad press
+    ///
+    /// DIV is reset either way
+    fn enter_stop(&mut self) {
+        if self.model.is_cgb() && self.speed_switch_armed {
+            self.double_speed = !self.double_speed;
+            self.speed_switch_armed = false;
+
+            // the CPU is paused while the clock settles, and DIV doesn't count during the switch
+            for _ in 0..SPEED_SWITCH_CYCLES {
+                self.tick();
+            }
+        } else {
+            self.stop = true;
         }
 
-        if self.regs.b != old_regs.b {
-            self.push_event(CpuEvent::Reg(CpuReg::B));
-        }
+        self.div = 0;
+    }
 
-        if self.regs.c != old_regs.c {
-            self.push_event(CpuEvent::Reg(CpuReg::C));
+    /// Push events for any changed registers
+    fn diff_regs(&mut self, old_regs: Registers) {
+        for reg in CpuReg::ALL {
+            if self.regs.get(reg) != old_regs.get(reg) {
+                self.push_event(CpuEvent::Reg(reg));
+            }
         }
+    }
 
-        if self.regs.d != old_regs.d {
-            self.push_event(CpuEvent::Reg(CpuReg::D));
-        }
+    /// Loads a byte from memory and ticks an M-cycle, checking it against Read watchpoints
+    ///
+    /// ### Return Variants
+    /// - `Ok(value)` if a byte was read successfully
+    /// - `Err(addr)` if the byte at the address was uninitialized, and `Self::allow_uninit` is false
+    fn mem_load(&mut self, addr: u16) -> Result<u8, CpuError> {
+        let out = self.fetch(addr);
 
-        if self.regs.h != old_regs.h {
-            self.push_event(CpuEvent::Reg(CpuReg::H));
+        if let Ok(value) = out {
+            self.check_watchpoints(addr, Access::Read, value, value);
         }
 
-        if self.regs.l != old_regs.l {
-            self.push_event(CpuEvent::Reg(CpuReg::L));
-        }
+        out
     }
 
-    /// Loads a byte from memory and ticks an M-cycle
+    /// Loads a byte of an instruction from memory and ticks an M-cycle, opcodes and operands aren't data so
+    /// they don't trigger watchpoints
     ///
     /// ### Return Variants
     /// - `Ok(value)` if a byte was read successfully
     /// - `Err(addr)` if the byte at the address was uninitialized, and `Self::allow_uninit` is false
-    fn mem_load(&mut self, addr: u16) -> Result<u8, CpuError> {
+    fn fetch(&mut self, addr: u16) -> Result<u8, CpuError> {
         fn mem_load_flat<T: Memory>(sys: &mut Cpu<T>, addr: u16) -> Result<u8, CpuError> {
             if let Some(out) = sys.memory.load(addr) {
                 if sys.debug {
@@ -796,33 +1115,84 @@ impl<T: Memory> Cpu<T> {
         self.push_event(CpuEvent::MemoryRead(addr));
 
         if self.memory.memory_type(addr) == MemoryType::Memory {
-            return mem_load_flat(self, addr);
+            mem_load_flat(self, addr)
+        } else {
+            match self.load_io(addr) {
+                Some(out) => Ok(out),
+                None => mem_load_flat(self, addr),
+            }
+        }
+    }
+
+    /// Pushes a `CpuEvent::Watch` for every watchpoint the access triggers
+    fn check_watchpoints(&mut self, addr: u16, access: Access, value: u8, old: u8) {
+        if self.breakpoint_controls.watchpoints.is_empty() {
+            return;
         }
 
-        let out = match addr {
+        let hits: Vec<WatchHit> = self
+            .breakpoint_controls
+            .watchpoints
+            .iter()
+            .filter(|wp| wp.matches(addr, access, value, old))
+            .map(|wp| WatchHit {
+                id: wp.id,
+                addr,
+                access,
+                value,
+                old,
+                pc: self.regs.pc,
+            })
+            .collect();
+
+        for hit in hits {
+            self.push_event(CpuEvent::Watch(hit));
+        }
+    }
+
+    /// Reads the IO registers whose state is kept by the CPU and PPU instead of memory
+    ///
+    /// ### Return Variants
+    /// - `Some(value)` if `addr` is one of those registers
+    /// - `None` if the value should come from memory
+    fn load_io(&self, addr: u16) -> Option<u8> {
+        match addr {
             memory::DIV => {
-                Ok((self.div >> 8) as u8)
+                Some((self.div >> 8) as u8)
             }
             memory::LY => {
-                if !self.ppu.enabled {
-                    Ok(0xFF)
+                if self.trace.as_ref().is_some_and(Trace::stubs_ly) {
+                    Some(0x90)
+                } else if !self.ppu.enabled {
+                    Some(0xFF)
                 } else {
-                    Ok(self.ppu.coords.y)
+                    Some(self.ppu.coords.y)
                 }
             }
             memory::JOYP => {
-                let gorp = self.joyp.serialize(self.host_input);
-                Ok(gorp)
+                Some(self.joyp.serialize(self.host_input))
             }
             memory::STAT => {
-                Ok(self.ppu.stat.into())
+                Some(self.ppu.stat.into())
             }
-            _ => {
-                mem_load_flat(self, addr)
+            memory::KEY1 if self.model.is_cgb() => {
+                Some(0x7E | (self.double_speed as u8) << 7 | self.speed_switch_armed as u8)
             }
+            _ => None,
+        }
+    }
+
+    /// Reads a byte the way the CPU would see it, without ticking or triggering breakpoints
+    ///
+    /// Uninitialized cells read as `0xFF`
+    pub fn peek(&self, addr: u16) -> u8 {
+        let io = if self.memory.memory_type(addr) == MemoryType::Io {
+            self.load_io(addr)
+        } else {
+            None
         };
-        
-        out
+
+        io.or_else(|| self.memory.load(addr)).unwrap_or(0xFF)
     }
 
     /// Sets a byte in memory and ticks an M-cycle
@@ -833,6 +1203,17 @@ impl<T: Memory> Cpu<T> {
         self.push_event(CpuEvent::MemoryWrite(addr));
         self.tick();
 
+        if !self.breakpoint_controls.watchpoints.is_empty() {
+            let old = self.peek(addr);
+            self.check_watchpoints(addr, Access::Write, value, old);
+        }
+
+        // interrupts can be requested by writing to IF directly
+        if addr == memory::IF {
+            let old = self.memory.load(memory::IF).unwrap_or(0);
+            self.push_interrupt_requests(old, value);
+        }
+
         if self.memory.memory_type(addr) == MemoryType::Memory {
             self.memory.set(addr, value);
             return;
@@ -852,11 +1233,23 @@ impl<T: Memory> Cpu<T> {
                 self.memory.set(addr, 0);
                 return;
             }
+            memory::KEY1 => {
+                // only the armed bit is writable, and only on CGB
+                if self.model.is_cgb() {
+                    self.speed_switch_armed = value & 1 > 0;
+                }
+                return;
+            }
             memory::LCDC => {
                 self.ppu.set_lcdc(value);
             }
             memory::STAT => {
-                self.ppu.set_stat(value);
+                let spurious = self.ppu.set_stat(value);
+
+                // DMG raises a STAT interrupt on writes during blanking
+                if spurious {
+                    self.request_interrupt(Interrupt::Stat);
+                }
             }
             memory::BGP => {
                 self.ppu.set_palette(value);
@@ -894,13 +1287,13 @@ impl<T: Memory> Cpu<T> {
         let low_addr = self.regs.pc.wrapping_add(1);
         let high_addr = self.regs.pc.wrapping_add(2);
 
-        let low = if let Ok(low) = self.mem_load(low_addr) {
+        let low = if let Ok(low) = self.fetch(low_addr) {
             low as u16
         } else {
             return Err(CpuError::MemoryLoadFail(low_addr));
         };
 
-        let high = if let Ok(high) = self.mem_load(high_addr) {
+        let high = if let Ok(high) = self.fetch(high_addr) {
             high as u16
         } else {
             return Err(CpuError::MemoryLoadFail(high_addr));
@@ -910,7 +1303,7 @@ impl<T: Memory> Cpu<T> {
     }
 
     fn load_d8(&mut self) -> Result<u8, CpuError> {
-        self.mem_load(self.regs.pc.wrapping_add(1))
+        self.fetch(self.regs.pc.wrapping_add(1))
     }
 
     fn load_s8(&mut self) -> Result<i8, CpuError> {
@@ -966,6 +1359,70 @@ impl<T: Memory> Cpu<T> {
         }
     }
 
+    /// Writes the state before the next instruction to the trace, if one is set and its conditions allow it
+    fn write_trace(&mut self) {
+        // taken out so the conditions can look at the rest of the CPU
+        let Some(mut trace) = self.trace.take() else {
+            return;
+        };
+
+        if !trace.started {
+            trace.started = trace.start.as_ref().is_none_or(|start| start.is_true(&*self, trace.lines));
+        }
+
+        if trace.started && !trace.stopped {
+            trace.stopped = trace.stop.as_ref().is_some_and(|stop| stop.is_true(&*self, trace.lines));
+        }
+
+        if trace.started && !trace.stopped {
+            match writeln!(trace.output, "{}", self.trace_line(trace.format)) {
+                Ok(()) => trace.lines += 1,
+                Err(e) => {
+                    trace.error = Some(e);
+                    trace.stopped = true;
+                }
+            }
+        }
+
+        self.trace = Some(trace);
+    }
+
+    /// Formats the state before the next instruction as a line of a trace
+    fn trace_line(&self, format: TraceFormat) -> String {
+        let regs = &self.regs;
+        let pc = regs.pc;
+
+        let mut line = format!(
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} \
+             PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            regs.a,
+            regs.f.as_byte(),
+            regs.b,
+            regs.c,
+            regs.d,
+            regs.e,
+            regs.h,
+            regs.l,
+            regs.sp,
+            pc,
+            self.peek(pc),
+            self.peek(pc.wrapping_add(1)),
+            self.peek(pc.wrapping_add(2)),
+            self.peek(pc.wrapping_add(3)),
+        );
+
+        if format == TraceFormat::Detailed {
+            line.push_str(&format!(
+                " CY:{} LY:{:02X} BANK:{:02X}",
+                self.tick,
+                self.peek(memory::LY),
+                self.memory.rom_bank()
+            ));
+        }
+
+        line
+    }
+
     fn dbg(&mut self, out: impl Display) {
         if self.debug {
             // print!("{}", out);
@@ -982,9 +1439,34 @@ impl<T: Memory> Cpu<T> {
     }
 }
 
+impl<T: Memory> ExprContext for Cpu<T> {
+    fn reg(&self, reg: CpuReg) -> u16 {
+        self.regs.get(reg)
+    }
+
+    fn flag(&self, flag: CpuFlag) -> bool {
+        match flag {
+            CpuFlag::Zero => self.regs.get_zf(),
+            CpuFlag::Subtract => self.regs.get_nf(),
+            CpuFlag::HalfCarry => self.regs.get_hf(),
+            CpuFlag::Carry => self.regs.get_cf(),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        Cpu::peek(self, addr)
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.memory.rom_bank()
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum CpuError {
     MemoryLoadFail(u16),
+    /// One of the unused opcodes (D3, DB, DD, E3, E4, EB-ED, F4, FC, FD) was executed at `pc`
+    IllegalOpcode { pc: u16, opcode: u8 },
 }
 
 impl fmt::Display for CpuError {
@@ -0,0 +1,543 @@
+use std::{collections::HashMap, fmt};
+
+use super::{condition::parse_number, Instruction, EXT_PREFIX};
+
+/// Assembles SM83 source into bytes, panicking if it's invalid
+///
+/// The program is assembled to run from 0100, where execution starts. Statements are separated by `/`,
+/// since everything passed to a macro ends up on one line:
+///
+/// ```
+/// let program = gbc::gbasm!(ld a, 5 / add b / halt);
+/// assert_eq!(program, vec![0x3E, 0x05, 0x80, 0x76]);
+/// ```
+///
+/// See `assemble()` for the syntax
+#[macro_export]
+macro_rules! gbasm {
+    ($($asm:tt)*) => {
+        $crate::assemble(stringify!($($asm)*), 0x0100).unwrap_or_else(|e| panic!("{e}"))
+    };
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AsmError {
+    /// A character that can't start a token, in the statement
+    UnexpectedChar(usize, char),
+    /// A statement that isn't shaped like labels followed by an instruction
+    Syntax(usize),
+    InvalidNumber(usize, String),
+    /// No instruction has the mnemonic and operands of the statement
+    UnknownInstruction(usize, String),
+    UnknownLabel(usize, String),
+    DuplicateLabel(usize, String),
+    /// An operand doesn't fit, such as a relative jump that's too far
+    OutOfRange(usize, i32),
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsmError::UnexpectedChar(at, c) => write!(f, "Statement {at}: unexpected character {c:?}"),
+            AsmError::Syntax(at) => write!(f, "Statement {at}: invalid syntax"),
+            AsmError::InvalidNumber(at, num) => write!(f, "Statement {at}: invalid number {num:?}"),
+            AsmError::UnknownInstruction(at, text) => write!(f, "Statement {at}: unknown instruction {text:?}"),
+            AsmError::UnknownLabel(at, label) => write!(f, "Statement {at}: unknown label {label:?}"),
+            AsmError::DuplicateLabel(at, label) => write!(f, "Statement {at}: label {label:?} is already defined"),
+            AsmError::OutOfRange(at, value) => write!(f, "Statement {at}: operand {value} is out of range"),
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Names that are registers or jump conditions, rather than labels
+const NAMES: [&str; 16] = ["a", "f", "b", "c", "d", "e", "h", "l", "af", "bc", "de", "hl", "sp", "nz", "z", "nc"];
+
+/// Mnemonics where `a` can be left out, like `add b`
+const ALU: [&str; 8] = ["add", "adc", "sub", "sbc", "and", "or", "xor", "cp"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Comma,
+    LBracket,
+    RBracket,
+    Plus,
+    Minus,
+    Colon,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '$' | '%' | '.')
+}
+
+/// Splits a statement into tokens
+fn tokenize(at: usize, src: &str) -> Result<Vec<Token>, AsmError> {
+    let mut out = Vec::new();
+    let mut chars = src.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        if is_word_char(c) {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek().filter(|&&c| is_word_char(c)) {
+                word.push(c);
+                chars.next();
+            }
+
+            // stringify! can put a space between a prefix and what it applies to
+            match out.last_mut() {
+                Some(Token::Word(prev)) if matches!(prev.as_str(), "$" | "%" | ".") => prev.push_str(&word),
+                _ => out.push(Token::Word(word)),
+            }
+            continue;
+        }
+
+        chars.next();
+        out.push(match c {
+            ',' => Token::Comma,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            ':' => Token::Colon,
+            _ => return Err(AsmError::UnexpectedChar(at, c)),
+        });
+    }
+
+    Ok(out)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Atom {
+    Num(i32),
+    Label(String),
+}
+
+/// A sum of numbers and labels
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Expr {
+    /// Each atom, and whether it's subtracted
+    terms: Vec<(bool, Atom)>,
+}
+
+impl Expr {
+    /// Parses `tokens`, with `scope` being the global label local labels belong to
+    fn parse(at: usize, tokens: &[Token], scope: &str) -> Result<Self, AsmError> {
+        let mut terms = Vec::new();
+        let mut negative = false;
+        let mut expecting_atom = true;
+
+        for token in tokens {
+            match (token, expecting_atom) {
+                (Token::Plus, _) => expecting_atom = true,
+                // unary minus flips the sign, binary minus starts a subtracted atom
+                (Token::Minus, true) => negative = !negative,
+                (Token::Minus, false) => {
+                    negative = true;
+                    expecting_atom = true;
+                }
+                (Token::Word(word), true) => {
+                    let atom = match parse_number(word) {
+                        Some(Ok(value)) => Atom::Num(value as i32),
+                        Some(Err(_)) => return Err(AsmError::InvalidNumber(at, word.clone())),
+                        None => Atom::Label(qualify(word, scope)),
+                    };
+
+                    terms.push((negative, atom));
+                    negative = false;
+                    expecting_atom = false;
+                }
+                _ => return Err(AsmError::Syntax(at)),
+            }
+        }
+
+        if expecting_atom {
+            return Err(AsmError::Syntax(at));
+        }
+
+        Ok(Self { terms })
+    }
+
+    /// The value of the expression if it has no labels
+    fn constant(&self) -> Option<i32> {
+        self.eval(&HashMap::new(), 0).ok()
+    }
+
+    fn eval(&self, labels: &HashMap<String, u16>, at: usize) -> Result<i32, AsmError> {
+        self.terms.iter().try_fold(0i32, |sum, (negative, atom)| {
+            let value = match atom {
+                Atom::Num(value) => *value,
+                Atom::Label(label) => match labels.get(label) {
+                    Some(&addr) => addr as i32,
+                    None => return Err(AsmError::UnknownLabel(at, label.clone())),
+                },
+            };
+
+            Ok(if *negative { sum - value } else { sum + value })
+        })
+    }
+}
+
+/// Turns `.local` labels into `global.local`, leaving global labels alone
+fn qualify(label: &str, scope: &str) -> String {
+    if label.starts_with('.') {
+        format!("{scope}{label}")
+    } else {
+        label.to_string()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Operand {
+    /// A register, condition, or register used as an address like `[hl+]`
+    Name(String),
+    Imm(Expr),
+    /// An address in brackets
+    Mem(Expr),
+    /// `sp+e8`
+    SpOffset(Expr),
+}
+
+impl Operand {
+    fn parse(at: usize, tokens: &[Token], scope: &str) -> Result<Self, AsmError> {
+        let word = |i: usize| match tokens.get(i) {
+            Some(Token::Word(word)) => Some(word.to_ascii_lowercase()),
+            _ => None,
+        };
+
+        if tokens.first() == Some(&Token::LBracket) {
+            if tokens.last() != Some(&Token::RBracket) || tokens.len() < 3 {
+                return Err(AsmError::Syntax(at));
+            }
+
+            let inner = &tokens[1..tokens.len() - 1];
+            let name = match (word(1).as_deref(), inner.get(1), inner.len()) {
+                (Some("hl" | "bc" | "de" | "c"), None, 1) => word(1),
+                (Some("hli"), None, 1) | (Some("hl"), Some(Token::Plus), 2) => Some("hl+".to_string()),
+                (Some("hld"), None, 1) | (Some("hl"), Some(Token::Minus), 2) => Some("hl-".to_string()),
+                _ => None,
+            };
+
+            return match name {
+                Some(name) => Ok(Operand::Name(format!("[{name}]"))),
+                None => Ok(Operand::Mem(Expr::parse(at, inner, scope)?)),
+            };
+        }
+
+        if word(0).as_deref() == Some("sp") && matches!(tokens.get(1), Some(Token::Plus | Token::Minus)) {
+            return Ok(Operand::SpOffset(Expr::parse(at, &tokens[1..], scope)?));
+        }
+
+        match word(0) {
+            Some(name) if tokens.len() == 1 && NAMES.contains(&name.as_str()) => Ok(Operand::Name(name)),
+            _ => Ok(Operand::Imm(Expr::parse(at, tokens, scope)?)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Imm {
+    N8,
+    N16,
+    /// Signed byte, relative to the next instruction for JR
+    E8,
+    /// Address in FF00-FFFF, stored as its low byte
+    A8,
+}
+
+/// An operand of an instruction, as printed by `Instruction`'s `Display`
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Pattern {
+    Name(String),
+    Imm(Imm),
+    Mem(Imm),
+    SpOffset,
+    /// A value that's part of the opcode, like the bit of `bit` or the address of `rst`
+    Const(i32),
+}
+
+impl Pattern {
+    fn parse(src: &str) -> Self {
+        match src {
+            "n8" => Pattern::Imm(Imm::N8),
+            "n16" | "a16" => Pattern::Imm(Imm::N16),
+            "e8" => Pattern::Imm(Imm::E8),
+            "[a16]" => Pattern::Mem(Imm::N16),
+            "[a8]" => Pattern::Mem(Imm::A8),
+            "sp+e8" => Pattern::SpOffset,
+            _ => match parse_number(src) {
+                Some(Ok(value)) => Pattern::Const(value as i32),
+                _ => Pattern::Name(src.to_string()),
+            },
+        }
+    }
+
+    fn matches(&self, operand: &Operand) -> bool {
+        match (self, operand) {
+            (Pattern::Name(pattern), Operand::Name(name)) => pattern == name,
+            (Pattern::Imm(_), Operand::Imm(_)) | (Pattern::Mem(_), Operand::Mem(_)) => true,
+            (Pattern::SpOffset, Operand::SpOffset(_)) => true,
+            (Pattern::Const(value), Operand::Imm(expr)) => expr.constant() == Some(*value),
+            _ => false,
+        }
+    }
+}
+
+/// An encoding of an instruction, built from the decoder so the two can't disagree
+struct Template {
+    prefixed: bool,
+    opcode: u8,
+    instruction: Instruction,
+    mnemonic: String,
+    operands: Vec<Pattern>,
+}
+
+fn templates() -> Vec<Template> {
+    (0..=0xFF)
+        .flat_map(|opcode| [(false, opcode), (true, opcode)])
+        .filter_map(|(prefixed, opcode)| {
+            let instruction = Instruction::from_byte(prefixed, opcode)?;
+            let text = instruction.to_string();
+            let (mnemonic, operands) = text.split_once(' ').unwrap_or((&text, ""));
+
+            Some(Template {
+                prefixed,
+                opcode,
+                instruction,
+                mnemonic: mnemonic.to_string(),
+                operands: operands.split(", ").filter(|o| !o.is_empty()).map(Pattern::parse).collect(),
+            })
+        })
+        .collect()
+}
+
+enum Item<'a> {
+    Instruction(&'a Template, Vec<Operand>),
+    /// `db` or `dw`, with the width of each value
+    Data(Vec<Expr>, u16),
+}
+
+/// Assembles SM83 source into bytes, as if it was placed at `origin`
+///
+/// The syntax follows RGBDS:
+/// - Statements are separated by newlines or `/`, and `;` starts a comment
+/// - `label:` defines a label, and `.label:` a label local to the last one without a dot
+/// - Numbers are decimal, or hex with a `$` or `0x` prefix, or binary with a `%` prefix, and operands can add
+///   and subtract numbers and labels
+/// - Memory operands use brackets like `[hl+]`, `a` can be left out of ALU instructions like `add b`, and
+///   `jr` takes the address it jumps to
+/// - `db` and `dw` insert bytes and little endian words
+///
+/// ### Return Variants
+/// - `Err(AsmError)` on the first statement that can't be assembled
+pub fn assemble(source: &str, origin: u16) -> Result<Vec<u8>, AsmError> {
+    let templates = templates();
+    let mut labels = HashMap::new();
+    let mut items = Vec::new();
+    let mut scope = String::new();
+    let mut addr = origin;
+
+    let statements = source
+        .lines()
+        .map(|line| line.split(';').next().unwrap_or_default())
+        .flat_map(|line| line.split('/'));
+
+    for (at, statement) in statements.enumerate().map(|(i, s)| (i + 1, s)) {
+        let mut tokens = &tokenize(at, statement)?[..];
+
+        // labels come before the instruction
+        while let [Token::Word(label), Token::Colon, rest @ ..] = tokens {
+            if !label.starts_with('.') {
+                scope = label.clone();
+            }
+
+            let label = qualify(label, &scope);
+            if labels.insert(label.clone(), addr).is_some() {
+                return Err(AsmError::DuplicateLabel(at, label));
+            }
+            tokens = rest;
+        }
+
+        let Some((mnemonic, rest)) = tokens.split_first() else {
+            continue;
+        };
+        let Token::Word(mnemonic) = mnemonic else {
+            return Err(AsmError::Syntax(at));
+        };
+        let mnemonic = mnemonic.to_ascii_lowercase();
+
+        let mut operands = Vec::new();
+        if !rest.is_empty() {
+            for tokens in rest.split(|t| *t == Token::Comma) {
+                operands.push(Operand::parse(at, tokens, &scope)?);
+            }
+        }
+
+        let item = match mnemonic.as_str() {
+            "db" | "dw" => {
+                let width = if mnemonic == "db" { 1 } else { 2 };
+                let values = operands
+                    .into_iter()
+                    .map(|operand| match operand {
+                        Operand::Imm(expr) => Ok(expr),
+                        _ => Err(AsmError::Syntax(at)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Item::Data(values, width)
+            }
+            _ => {
+                if ALU.contains(&mnemonic.as_str()) && operands.len() == 1 {
+                    operands.insert(0, Operand::Name("a".to_string()));
+                }
+
+                let template = templates.iter().find(|t| {
+                    t.mnemonic == mnemonic
+                        && t.operands.len() == operands.len()
+                        && t.operands.iter().zip(&operands).all(|(p, o)| p.matches(o))
+                });
+
+                match template {
+                    Some(template) => Item::Instruction(template, operands),
+                    None => return Err(AsmError::UnknownInstruction(at, statement.trim().to_string())),
+                }
+            }
+        };
+
+        let size = match &item {
+            Item::Instruction(template, _) => template.instruction.size(),
+            Item::Data(values, width) => values.len() as u16 * width,
+        };
+
+        items.push((at, addr, item));
+        addr = addr.wrapping_add(size);
+    }
+
+    let mut out = Vec::new();
+    for (at, addr, item) in items {
+        match item {
+            Item::Instruction(template, operands) => {
+                let start = out.len();
+                if template.prefixed {
+                    out.push(EXT_PREFIX);
+                }
+                out.push(template.opcode);
+
+                for (pattern, operand) in template.operands.iter().zip(&operands) {
+                    let (imm, expr) = match (pattern, operand) {
+                        (Pattern::Imm(imm), Operand::Imm(expr)) | (Pattern::Mem(imm), Operand::Mem(expr)) => {
+                            (*imm, expr)
+                        }
+                        (Pattern::SpOffset, Operand::SpOffset(expr)) => (Imm::E8, expr),
+                        _ => continue,
+                    };
+
+                    let value = expr.eval(&labels, at)?;
+                    let relative = matches!(template.instruction, Instruction::JR(_));
+                    encode(&mut out, imm, value, relative.then_some(addr), at)?;
+                }
+
+                // STOP is followed by a byte that's ignored
+                out.resize(start + template.instruction.size() as usize, 0);
+            }
+            Item::Data(values, width) => {
+                for expr in values {
+                    let value = expr.eval(&labels, at)?;
+                    let imm = if width == 1 { Imm::N8 } else { Imm::N16 };
+                    encode(&mut out, imm, value, None, at)?;
+                }
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Appends `value` as an `imm` operand, `relative_to` is the address of a JR
+fn encode(out: &mut Vec<u8>, imm: Imm, value: i32, relative_to: Option<u16>, at: usize) -> Result<(), AsmError> {
+    let value = match relative_to {
+        Some(addr) => value - (addr as i32 + 2),
+        None => value,
+    };
+
+    let fits = match imm {
+        Imm::N8 => (-0x80..=0xFF).contains(&value),
+        Imm::N16 => (-0x8000..=0xFFFF).contains(&value),
+        Imm::E8 => (-0x80..=0x7F).contains(&value),
+        Imm::A8 => (0x00..=0xFF).contains(&value) || (0xFF00..=0xFFFF).contains(&value),
+    };
+
+    if !fits {
+        return Err(AsmError::OutOfRange(at, value));
+    }
+
+    match imm {
+        Imm::N16 => out.extend_from_slice(&(value as u16).to_le_bytes()),
+        _ => out.push(value as u8),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, AsmError};
+
+    #[test]
+    fn instructions() {
+        let program = assemble(
+            "ld a, $3C\n\
+             ld [hl+], a / ldh [$FF44], a ; comment\n\
+             add b / sub a, 1 / bit 7, h / rst $38\n\
+             ld hl, sp-2 / add sp, 4 / ld [$C000], sp / stop / jp hl",
+            0x0100,
+        );
+
+        assert_eq!(
+            program,
+            Ok(vec![
+                0x3E, 0x3C, 0x22, 0xE0, 0x44, 0x80, 0xD6, 0x01, 0xCB, 0x7C, 0xFF, 0xF8, 0xFE, 0xE8, 0x04, 0x08,
+                0x00, 0xC0, 0x10, 0x00, 0xE9,
+            ])
+        );
+    }
+
+    #[test]
+    fn labels() {
+        let program = assemble(
+            "main:\n\
+             .loop: dec a\n\
+             jr nz, .loop\n\
+             call sub / jr main\n\
+             sub: ret\n\
+             db 1, 2 / dw sub",
+            0x0150,
+        );
+
+        assert_eq!(
+            program,
+            Ok(vec![0x3D, 0x20, 0xFD, 0xCD, 0x58, 0x01, 0x18, 0xF8, 0xC9, 0x01, 0x02, 0x58, 0x01])
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(assemble("ld a, b, c", 0), Err(AsmError::UnknownInstruction(1, "ld a, b, c".to_string())));
+        assert_eq!(assemble("jp nowhere", 0), Err(AsmError::UnknownLabel(1, "nowhere".to_string())));
+        assert_eq!(assemble("a: / a:", 0), Err(AsmError::DuplicateLabel(2, "a".to_string())));
+        assert_eq!(assemble("nop / ld a, 256", 0), Err(AsmError::OutOfRange(2, 256)));
+        assert_eq!(assemble("jr $0200", 0), Err(AsmError::OutOfRange(1, 0x01FE)));
+    }
+
+    #[test]
+    fn macro_syntax() {
+        let program = crate::gbasm!(ld a, $05 / .loop: dec a / jr nz, .loop / halt);
+        assert_eq!(program, vec![0x3E, 0x05, 0x3D, 0x20, 0xFD, 0x76]);
+    }
+}
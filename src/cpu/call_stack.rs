@@ -0,0 +1,148 @@
+use super::Interrupt;
+
+/// Frames past this are dropped from the bottom of the stack, so runaway recursion can't grow it forever
+const MAX_DEPTH: usize = 1024;
+
+/// A routine that was entered by CALL, RST or an interrupt and hasn't returned yet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// Address of the CALL or RST, or of the instruction the interrupt was serviced before
+    pub call_site: u16,
+    /// Address of the routine
+    pub target: u16,
+    /// ROM bank mapped into 4000-7FFF when the routine was entered
+    pub bank: usize,
+    /// The interrupt that entered the routine, `None` for CALL and RST
+    pub interrupt: Option<Interrupt>,
+    /// Where the routine returns to
+    pub return_addr: u16,
+    /// SP once the routine has returned, the return address is stored just below it
+    pub return_sp: u16,
+}
+
+/// Stack manipulation the call stack couldn't follow
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackMismatch {
+    /// SP moved above the return address of this many frames without them returning, so they were dropped
+    Discarded(usize),
+    /// A RET or RETI jumped to an address no frame returns to, such as one pushed by hand for a trampoline
+    UnknownReturn(u16),
+}
+
+/// Shadow call stack kept alongside the real one, for backtraces
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CallStack {
+    /// Outermost frame first
+    pub frames: Vec<Frame>,
+    /// Number of times the stack was manipulated in a way the call stack couldn't follow
+    pub mismatches: usize,
+    /// The most recent mismatch, along with PC when it happened
+    pub last_mismatch: Option<(u16, StackMismatch)>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a routine being entered by the instruction at `pc`
+    pub fn enter(&mut self, pc: u16, frame: Frame) {
+        self.discard_above(pc, frame.return_sp);
+
+        if self.frames.len() == MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    /// Records a RET or RETI at `pc` returning to `return_addr`, with SP at `sp` after the return address was popped
+    pub fn exit(&mut self, pc: u16, return_addr: u16, sp: u16) {
+        match self.frames.iter().rposition(|f| f.return_addr == return_addr && f.return_sp == sp) {
+            Some(i) => {
+                let discarded = self.frames.len() - 1 - i;
+                self.frames.truncate(i);
+
+                if discarded > 0 {
+                    self.flag(pc, StackMismatch::Discarded(discarded));
+                }
+            }
+            None => {
+                self.discard_above(pc, sp);
+                self.flag(pc, StackMismatch::UnknownReturn(return_addr));
+            }
+        }
+    }
+
+    /// Drops every frame whose return address is at or above `sp`, since it can't be returned through any more
+    fn discard_above(&mut self, pc: u16, sp: u16) {
+        let kept = self.frames.iter().position(|f| f.return_sp <= sp).unwrap_or(self.frames.len());
+        let discarded = self.frames.len() - kept;
+
+        if discarded > 0 {
+            self.frames.truncate(kept);
+            self.flag(pc, StackMismatch::Discarded(discarded));
+        }
+    }
+
+    fn flag(&mut self, pc: u16, mismatch: StackMismatch) {
+        self.mismatches += 1;
+        self.last_mismatch = Some((pc, mismatch));
+    }
+
+    /// Clears the stack and any recorded mismatches
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CallStack, Frame, StackMismatch};
+
+    fn frame(call_site: u16, return_sp: u16) -> Frame {
+        Frame {
+            call_site,
+            target: 0x4000,
+            bank: 1,
+            interrupt: None,
+            return_addr: call_site + 3,
+            return_sp,
+        }
+    }
+
+    #[test]
+    fn balanced() {
+        let mut stack = CallStack::new();
+        stack.enter(0x0150, frame(0x0150, 0xFFFE));
+        stack.enter(0x4000, frame(0x4000, 0xFFFC));
+        assert_eq!(stack.frames.len(), 2);
+
+        stack.exit(0x4100, 0x4003, 0xFFFC);
+        stack.exit(0x4200, 0x0153, 0xFFFE);
+        assert!(stack.frames.is_empty());
+        assert_eq!(stack.mismatches, 0);
+    }
+
+    #[test]
+    fn mismatched() {
+        let mut stack = CallStack::new();
+        stack.enter(0x0150, frame(0x0150, 0xFFFE));
+        stack.enter(0x4000, frame(0x4000, 0xFFFC));
+
+        // a pushed address is returned to, the frames are left alone
+        stack.exit(0x4010, 0x1234, 0xFFFA);
+        assert_eq!(stack.frames.len(), 2);
+        assert_eq!(stack.last_mismatch, Some((0x4010, StackMismatch::UnknownReturn(0x1234))));
+
+        // returning through the outer frame drops the inner one
+        stack.exit(0x4020, 0x0153, 0xFFFE);
+        assert!(stack.frames.is_empty());
+        assert_eq!(stack.last_mismatch, Some((0x4020, StackMismatch::Discarded(1))));
+
+        // SP reset by hand before calling again
+        stack.enter(0x0150, frame(0x0150, 0xFFF0));
+        stack.enter(0x0200, frame(0x0200, 0xFFFE));
+        assert_eq!(stack.frames, vec![frame(0x0200, 0xFFFE)]);
+        assert_eq!(stack.mismatches, 3);
+    }
+}
@@ -0,0 +1,447 @@
+use std::{fmt, num::ParseIntError, str::FromStr};
+
+use super::{CpuFlag, CpuReg};
+
+/// An expression over the system state, used as the condition of a conditional breakpoint
+///
+/// Parsed from strings like `pc == $0150 && a > 3`:
+/// - Numbers are decimal, or hex with a `$` or `0x` prefix, or binary with a `%` prefix
+/// - `a`, `f`, `b`, `c`, `d`, `e`, `h`, `l`, `sp`, `pc`, `af`, `bc`, `de` and `hl` are registers
+/// - `zf`, `nf`, `hf` and `cf` are flags, and are either `0` or `1`
+/// - `bank` is the ROM bank mapped into 4000-7FFF, and `hits` is the breakpoint's hit count
+/// - `[expr]` reads the byte at `expr` without side effects
+/// - Operators are `||`, `&&`, `==`, `!=`, `<`, `<=`, `>`, `>=`, `|`, `^`, `&`, `+`, `-` and `!`,
+///   with the same precedence as in Rust
+///
+/// All values are 16 bit and wrap, comparisons and logical operators evaluate to `0` or `1`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Num(u16),
+    Reg(CpuReg),
+    Flag(CpuFlag),
+    /// The byte at the address
+    Mem(Box<Expr>),
+    RomBank,
+    Hits,
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Sub,
+}
+
+impl BinOp {
+    /// Binding power, higher binds tighter
+    fn precedence(&self) -> u8 {
+        match self {
+            BinOp::Or => 1,
+            BinOp::And => 2,
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 3,
+            BinOp::BitOr => 4,
+            BinOp::BitXor => 5,
+            BinOp::BitAnd => 6,
+            BinOp::Add | BinOp::Sub => 7,
+        }
+    }
+}
+
+/// The state an expression is evaluated against
+pub trait ExprContext {
+    fn reg(&self, reg: CpuReg) -> u16;
+    fn flag(&self, flag: CpuFlag) -> bool;
+    /// Reads a byte without ticking the system or triggering breakpoints
+    fn peek(&self, addr: u16) -> u8;
+    fn rom_bank(&self) -> usize;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExprError {
+    /// A character that can't start a token, at the byte offset
+    UnexpectedChar(usize, char),
+    /// A token that doesn't fit where it is, at the byte offset
+    UnexpectedToken(usize),
+    /// The expression ended early
+    UnexpectedEnd,
+    UnknownName(String),
+    InvalidNumber(String),
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExprError::UnexpectedChar(at, c) => write!(f, "Unexpected character {c:?} at {at}"),
+            ExprError::UnexpectedToken(at) => write!(f, "Unexpected token at {at}"),
+            ExprError::UnexpectedEnd => write!(f, "Unexpected end of expression"),
+            ExprError::UnknownName(name) => write!(f, "Unknown name {name:?}"),
+            ExprError::InvalidNumber(num) => write!(f, "Invalid number {num:?}"),
+        }
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Num(u16),
+    Name(String),
+    Op(BinOp),
+    Not,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+}
+
+/// Splits `src` into tokens, each paired with its byte offset
+fn tokenize(src: &str) -> Result<Vec<(usize, Token)>, ExprError> {
+    let mut out = Vec::new();
+    let mut chars = src.char_indices().peekable();
+
+    while let Some(&(at, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        if c.is_ascii_alphanumeric() || c == '$' || c == '%' || c == '_' {
+            let mut word = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if !(c.is_ascii_alphanumeric() || c == '$' || c == '%' || c == '_') {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+
+            out.push((at, parse_word(word)?));
+            continue;
+        }
+
+        chars.next();
+        let next = chars.peek().map(|&(_, c)| c);
+
+        // two character operators
+        let double = match (c, next) {
+            ('|', Some('|')) => Some(Token::Op(BinOp::Or)),
+            ('&', Some('&')) => Some(Token::Op(BinOp::And)),
+            ('=', Some('=')) => Some(Token::Op(BinOp::Eq)),
+            ('!', Some('=')) => Some(Token::Op(BinOp::Ne)),
+            ('<', Some('=')) => Some(Token::Op(BinOp::Le)),
+            ('>', Some('=')) => Some(Token::Op(BinOp::Ge)),
+            _ => None,
+        };
+
+        if let Some(token) = double {
+            chars.next();
+            out.push((at, token));
+            continue;
+        }
+
+        let token = match c {
+            '<' => Token::Op(BinOp::Lt),
+            '>' => Token::Op(BinOp::Gt),
+            '|' => Token::Op(BinOp::BitOr),
+            '^' => Token::Op(BinOp::BitXor),
+            '&' => Token::Op(BinOp::BitAnd),
+            '+' => Token::Op(BinOp::Add),
+            '-' => Token::Op(BinOp::Sub),
+            '!' => Token::Not,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            _ => return Err(ExprError::UnexpectedChar(at, c)),
+        };
+
+        out.push((at, token));
+    }
+
+    Ok(out)
+}
+
+/// Turns a run of word characters into a number or a name
+fn parse_word(word: String) -> Result<Token, ExprError> {
+    let lower = word.to_ascii_lowercase();
+
+    match parse_number(&lower) {
+        Some(Ok(value)) => Ok(Token::Num(value)),
+        Some(Err(_)) => Err(ExprError::InvalidNumber(word)),
+        None if lower.contains(['$', '%']) => Err(ExprError::InvalidNumber(word)),
+        None => Ok(Token::Name(lower)),
+    }
+}
+
+/// Parses a decimal, `$` or `0x` prefixed hex, or `%` prefixed binary number
+///
+/// ### Return Variants
+/// - `None` if `word` isn't shaped like a number
+/// - `Some(Err(_))` if it is, but doesn't fit in 16 bits or has invalid digits
+pub(super) fn parse_number(word: &str) -> Option<Result<u16, ParseIntError>> {
+    let lower = word.to_ascii_lowercase();
+
+    if let Some(hex) = lower.strip_prefix('$').or_else(|| lower.strip_prefix("0x")) {
+        Some(u16::from_str_radix(hex, 16))
+    } else if let Some(bin) = lower.strip_prefix('%') {
+        Some(u16::from_str_radix(bin, 2))
+    } else if lower.starts_with(|c: char| c.is_ascii_digit()) {
+        Some(lower.parse())
+    } else {
+        None
+    }
+}
+
+/// Precedence climbing parser over a list of tokens
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn next(&mut self) -> Result<(usize, Token), ExprError> {
+        let token = self.tokens.get(self.pos).cloned().ok_or(ExprError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ExprError> {
+        let (at, token) = self.next()?;
+        if token == expected {
+            Ok(())
+        } else {
+            Err(ExprError::UnexpectedToken(at))
+        }
+    }
+
+    /// Parses binary operators that bind at least as tightly as `min`
+    fn binary(&mut self, min: u8) -> Result<Expr, ExprError> {
+        let mut lhs = self.unary()?;
+
+        while let Some(&Token::Op(op)) = self.peek() {
+            if op.precedence() < min {
+                break;
+            }
+
+            self.pos += 1;
+            let rhs = self.binary(op.precedence() + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ExprError> {
+        let (at, token) = self.next()?;
+
+        match token {
+            Token::Num(value) => Ok(Expr::Num(value)),
+            Token::Name(name) => name_to_expr(&name).ok_or(ExprError::UnknownName(name)),
+            Token::Not => Ok(Expr::Not(Box::new(self.unary()?))),
+            Token::LParen => {
+                let inner = self.binary(0)?;
+                self.expect(Token::RParen)?;
+                Ok(inner)
+            }
+            Token::LBracket => {
+                let inner = self.binary(0)?;
+                self.expect(Token::RBracket)?;
+                Ok(Expr::Mem(Box::new(inner)))
+            }
+            _ => Err(ExprError::UnexpectedToken(at)),
+        }
+    }
+}
+
+fn name_to_expr(name: &str) -> Option<Expr> {
+    let expr = match name {
+        "a" => Expr::Reg(CpuReg::A),
+        "f" => Expr::Reg(CpuReg::F),
+        "b" => Expr::Reg(CpuReg::B),
+        "c" => Expr::Reg(CpuReg::C),
+        "d" => Expr::Reg(CpuReg::D),
+        "e" => Expr::Reg(CpuReg::E),
+        "h" => Expr::Reg(CpuReg::H),
+        "l" => Expr::Reg(CpuReg::L),
+        "sp" => Expr::Reg(CpuReg::SP),
+        "pc" => Expr::Reg(CpuReg::PC),
+        "af" => Expr::Reg(CpuReg::AF),
+        "bc" => Expr::Reg(CpuReg::BC),
+        "de" => Expr::Reg(CpuReg::DE),
+        "hl" => Expr::Reg(CpuReg::HL),
+        "zf" => Expr::Flag(CpuFlag::Zero),
+        "nf" => Expr::Flag(CpuFlag::Subtract),
+        "hf" => Expr::Flag(CpuFlag::HalfCarry),
+        "cf" => Expr::Flag(CpuFlag::Carry),
+        "bank" => Expr::RomBank,
+        "hits" => Expr::Hits,
+        _ => return None,
+    };
+
+    Some(expr)
+}
+
+impl Expr {
+    /// Parses an expression from a string, see `Expr` for the syntax
+    ///
+    /// ### Return Variants
+    /// - `Err(ExprError)` if `src` isn't a single valid expression
+    pub fn parse(src: &str) -> Result<Self, ExprError> {
+        let mut parser = Parser {
+            tokens: tokenize(src)?,
+            pos: 0,
+        };
+
+        let out = parser.binary(0)?;
+        match parser.tokens.get(parser.pos) {
+            Some(&(at, _)) => Err(ExprError::UnexpectedToken(at)),
+            None => Ok(out),
+        }
+    }
+
+    /// Evaluates the expression against `ctx`, with `hits` as the value of `hits`
+    pub fn eval(&self, ctx: &impl ExprContext, hits: u32) -> u16 {
+        match self {
+            Expr::Num(value) => *value,
+            Expr::Reg(reg) => ctx.reg(*reg),
+            Expr::Flag(flag) => ctx.flag(*flag) as u16,
+            Expr::Mem(addr) => ctx.peek(addr.eval(ctx, hits)) as u16,
+            Expr::RomBank => ctx.rom_bank() as u16,
+            Expr::Hits => hits.min(u16::MAX as u32) as u16,
+            Expr::Not(inner) => (inner.eval(ctx, hits) == 0) as u16,
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = lhs.eval(ctx, hits);
+
+                // logical operators short circuit, so `[hl]` isn't read when it doesn't matter
+                match op {
+                    BinOp::Or if lhs != 0 => return 1,
+                    BinOp::And if lhs == 0 => return 0,
+                    _ => {}
+                }
+
+                let rhs = rhs.eval(ctx, hits);
+                match op {
+                    BinOp::Or | BinOp::And => (rhs != 0) as u16,
+                    BinOp::Eq => (lhs == rhs) as u16,
+                    BinOp::Ne => (lhs != rhs) as u16,
+                    BinOp::Lt => (lhs < rhs) as u16,
+                    BinOp::Le => (lhs <= rhs) as u16,
+                    BinOp::Gt => (lhs > rhs) as u16,
+                    BinOp::Ge => (lhs >= rhs) as u16,
+                    BinOp::BitOr => lhs | rhs,
+                    BinOp::BitXor => lhs ^ rhs,
+                    BinOp::BitAnd => lhs & rhs,
+                    BinOp::Add => lhs.wrapping_add(rhs),
+                    BinOp::Sub => lhs.wrapping_sub(rhs),
+                }
+            }
+        }
+    }
+
+    /// Returns whether the expression evaluates to something other than `0`
+    pub fn is_true(&self, ctx: &impl ExprContext, hits: u32) -> bool {
+        self.eval(ctx, hits) != 0
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ExprError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::{CpuFlag, CpuReg, Registers};
+
+    use super::{BinOp, Expr, ExprContext, ExprError};
+
+    struct Regs(Registers);
+
+    impl ExprContext for Regs {
+        fn reg(&self, reg: CpuReg) -> u16 {
+            self.0.get(reg)
+        }
+
+        fn flag(&self, flag: CpuFlag) -> bool {
+            match flag {
+                CpuFlag::Zero => self.0.f.zero,
+                CpuFlag::Subtract => self.0.f.subtract,
+                CpuFlag::HalfCarry => self.0.f.half_carry,
+                CpuFlag::Carry => self.0.f.carry,
+            }
+        }
+
+        fn peek(&self, addr: u16) -> u8 {
+            // every byte reads as its low address byte
+            addr as u8
+        }
+
+        fn rom_bank(&self) -> usize {
+            3
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let expr = Expr::parse("pc == $0150 && a > 3").unwrap();
+        assert_eq!(
+            expr,
+            Expr::Binary(
+                BinOp::And,
+                Box::new(Expr::Binary(
+                    BinOp::Eq,
+                    Box::new(Expr::Reg(CpuReg::PC)),
+                    Box::new(Expr::Num(0x0150)),
+                )),
+                Box::new(Expr::Binary(
+                    BinOp::Gt,
+                    Box::new(Expr::Reg(CpuReg::A)),
+                    Box::new(Expr::Num(3)),
+                )),
+            )
+        );
+
+        assert_eq!(Expr::parse("pc =="), Err(ExprError::UnexpectedEnd));
+        assert_eq!(Expr::parse("pc # 3"), Err(ExprError::UnexpectedChar(3, '#')));
+        assert_eq!(Expr::parse("ix == 3"), Err(ExprError::UnknownName("ix".to_string())));
+        assert_eq!(Expr::parse("$FG"), Err(ExprError::InvalidNumber("$FG".to_string())));
+        assert_eq!(Expr::parse("[hl] 3"), Err(ExprError::UnexpectedToken(5)));
+    }
+
+    #[test]
+    fn eval() {
+        let mut regs = Registers::new();
+        regs.pc = 0x0150;
+        regs.a = 4;
+        regs.set_hl(0xC012);
+        let ctx = Regs(regs);
+
+        let check = |src: &str| Expr::parse(src).unwrap().eval(&ctx, 2);
+
+        assert_eq!(check("pc == $0150 && a > 3"), 1);
+        assert_eq!(check("pc == $0150 && a > 4"), 0);
+        assert_eq!(check("[hl] == $12 || [$FF44] == 0"), 1);
+        assert_eq!(check("zf && cf && bank == 3 && hits >= 2"), 1);
+        assert_eq!(check("!(a - 4) & %1111"), 1);
+        assert_eq!(check("0x10 - 0x11"), 0xFFFF);
+    }
+}
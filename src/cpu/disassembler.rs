@@ -0,0 +1,156 @@
+use std::ops::RangeInclusive;
+
+use crate::memory::Memory;
+
+use super::{Instruction, EXT_PREFIX};
+
+/// A single disassembled instruction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Disassembly {
+    pub addr: u16,
+    /// ROM bank the instruction was read from, `None` outside of ROM
+    pub bank: Option<usize>,
+    /// The opcode, including the CB prefix, followed by its operands
+    pub bytes: Vec<u8>,
+    /// `None` for illegal opcodes, which are disassembled as a single `db`
+    pub instruction: Option<Instruction>,
+    /// The instruction as SM83 assembly, such as `ld a, $3C`
+    pub text: String,
+}
+
+impl Disassembly {
+    /// Length of the instruction in bytes
+    pub fn size(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+}
+
+/// Disassembles the instruction at `addr`
+///
+/// 4000-7FFF is read from ROM bank `bank` if it's given, and from the currently mapped bank otherwise.
+/// Uninitialized memory reads as `0xFF`
+pub fn disassemble<T: Memory + ?Sized>(memory: &T, addr: u16, bank: Option<usize>) -> Disassembly {
+    let rom_bank = match addr {
+        0x0000..=0x3FFF => Some(0),
+        0x4000..=0x7FFF => Some(bank.unwrap_or_else(|| memory.rom_bank())),
+        _ => None,
+    };
+
+    let read = |offset: u16| {
+        let addr = addr.wrapping_add(offset);
+
+        match (addr, bank) {
+            (0x4000..=0x7FFF, Some(bank)) => memory.load_rom_bank(bank, addr),
+            _ => memory.load(addr),
+        }
+        .unwrap_or(0xFF)
+    };
+
+    let opcode = read(0);
+    let (prefixed, operands_start, instruction) = if opcode == EXT_PREFIX {
+        (true, 2, Instruction::from_byte(true, read(1)))
+    } else {
+        (false, 1, Instruction::from_byte(false, opcode))
+    };
+
+    let Some(instruction) = instruction else {
+        return Disassembly {
+            addr,
+            bank: rom_bank,
+            bytes: vec![opcode],
+            instruction: None,
+            text: format!("db ${opcode:02X}"),
+        };
+    };
+
+    let bytes: Vec<u8> = (0..instruction.size()).map(read).collect();
+    let operands = if prefixed { &[] } else { &bytes[operands_start..] };
+
+    Disassembly {
+        addr,
+        bank: rom_bank,
+        text: instruction.to_asm(addr, operands),
+        bytes,
+        instruction: Some(instruction),
+    }
+}
+
+/// Disassembles every instruction starting in `range`, one after another from its start
+///
+/// See `disassemble()` for how `bank` is used
+pub fn disassemble_range<T: Memory + ?Sized>(
+    memory: &T,
+    range: RangeInclusive<u16>,
+    bank: Option<usize>,
+) -> Vec<Disassembly> {
+    let mut out = Vec::new();
+    let mut addr = *range.start();
+
+    while range.contains(&addr) {
+        let instruction = disassemble(memory, addr, bank);
+        let (next, overflowed) = addr.overflowing_add(instruction.size());
+        out.push(instruction);
+
+        if overflowed {
+            break;
+        }
+        addr = next;
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cpu::Instruction,
+        memory::{
+            mbc::{Mbc5Features, MbcSelector, RamSize, RomSize},
+            FlatMemory, Memory, Mmu,
+        },
+        Model,
+    };
+
+    use super::{disassemble, disassemble_range};
+
+    #[test]
+    fn range() {
+        let mut memory = FlatMemory::new();
+        memory.splice(0x0150, &[0x3E, 0x3C, 0x20, 0xFC, 0xCB, 0x7C, 0xD3, 0xC3, 0x50, 0x01]);
+
+        let text: Vec<(u16, String)> = disassemble_range(&memory, 0x0150..=0x0157, None)
+            .into_iter()
+            .map(|d| (d.addr, d.text))
+            .collect();
+
+        assert_eq!(
+            text,
+            vec![
+                (0x0150, "ld a, $3C".to_string()),
+                (0x0152, "jr nz, $0150".to_string()),
+                (0x0154, "bit 7, h".to_string()),
+                (0x0156, "db $D3".to_string()),
+                (0x0157, "jp $0150".to_string()),
+            ]
+        );
+
+        let jp = disassemble(&memory, 0x0157, None);
+        assert_eq!(jp.bytes, vec![0xC3, 0x50, 0x01]);
+        assert!(matches!(jp.instruction, Some(Instruction::JP(_))));
+    }
+
+    #[test]
+    fn banked() {
+        let mut mmu = Mmu::new(MbcSelector::Mbc5(RomSize::Two, RamSize::Zero, Mbc5Features::default()), Model::Dmg);
+        let mut rom = vec![0; 0x4000 * 8];
+        rom[0x4000 * 3] = 0x18;
+        rom[0x4000 * 3 + 1] = 0xFE;
+        mmu.load_rom(&rom);
+
+        let mapped = disassemble(&mmu, 0x4000, None);
+        assert_eq!((mapped.bank, mapped.text.as_str()), (Some(1), "nop"));
+
+        let banked = disassemble(&mmu, 0x4000, Some(3));
+        assert_eq!((banked.bank, banked.text.as_str()), (Some(3), "jr $4000"));
+    }
+}
@@ -4,6 +4,7 @@ mod arithmetic;
 mod bitwise;
 mod control;
 mod decode;
+mod display;
 mod load;
 mod stack;
 
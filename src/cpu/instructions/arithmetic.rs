@@ -269,13 +269,14 @@ mod tests {
         cpu::{instructions::WordArithmeticTarget, ArithmeticTarget, Cpu, Instruction},
         memory::FlatMemory,
         ppu::Ppu,
+        Model,
     };
 
     fn init() -> Cpu<FlatMemory> {
         let mmu = FlatMemory::new();
-        let ppu = Ppu::new();
+        let ppu = Ppu::new(Model::Dmg);
 
-        Cpu::new(mmu, ppu, false, true)
+        Cpu::new(mmu, ppu, Model::Dmg, false, true)
     }
 
     // ---------- 8 bit ----------
@@ -318,13 +318,14 @@ mod tests {
         },
         memory::FlatMemory,
         ppu::Ppu,
+        Model,
     };
 
     fn init() -> Cpu<FlatMemory> {
         let mmu = FlatMemory::new();
-        let ppu = Ppu::new();
+        let ppu = Ppu::new(Model::Dmg);
 
-        Cpu::new(mmu, ppu, false, true)
+        Cpu::new(mmu, ppu, Model::Dmg, false, true)
     }
 
     #[test]
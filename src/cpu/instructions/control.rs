@@ -1,4 +1,4 @@
-use crate::{cpu::{Cpu, CpuError}, memory::Memory};
+use crate::{cpu::{Cpu, CpuError, Frame}, memory::Memory};
 
 use super::JumpTest;
 
@@ -58,7 +58,7 @@ impl<T: Memory> Cpu<T> {
         };
 
         if jump {
-            self.pop_word()
+            self.pop_return()
         } else {
             self.tick();
             Ok(self.regs.pc.wrapping_add(1))
@@ -69,7 +69,30 @@ impl<T: Memory> Cpu<T> {
     pub(crate) fn reti(&mut self) -> Result<u16, CpuError> {
         self.regs.ime = true;
 
-        self.pop_word()
+        self.pop_return()
+    }
+
+    /// Pops a return address, and unwinds the call stack to the routine it belongs to
+    fn pop_return(&mut self) -> Result<u16, CpuError> {
+        let addr = self.pop_word()?;
+        self.call_stack.exit(self.regs.pc, addr, self.regs.sp);
+
+        Ok(addr)
+    }
+
+    /// Pushes the return address of a CALL or RST at PC, and records the routine being entered
+    fn push_call(&mut self, return_addr: u16, target: u16) {
+        let return_sp = self.regs.sp;
+        self.push_word(return_addr);
+
+        self.call_stack.enter(self.regs.pc, Frame {
+            call_site: self.regs.pc,
+            target,
+            bank: self.memory.rom_bank(),
+            interrupt: None,
+            return_addr,
+            return_sp,
+        });
     }
 
     /// Pushes PC to the stack and jumps to an immediate address
@@ -85,8 +108,9 @@ impl<T: Memory> Cpu<T> {
         let out = self.load_a16();
 
         if jump {
-            self.push_word(self.regs.pc.wrapping_add(3));
-            out
+            let target = out?;
+            self.push_call(self.regs.pc.wrapping_add(3), target);
+            Ok(target)
         } else {
             Ok(self.regs.pc.wrapping_add(3))
         }
@@ -101,10 +125,11 @@ impl<T: Memory> Cpu<T> {
             panic!("RST operand out of range: `{to}`. Valid range is 0..=7");
         }
 
-        self.push_word(self.regs.pc.wrapping_add(1));
-
         // We're jumping to the nth byte, so we can just use it as an address directly
-        to as u16 * 0x8
+        let target = to as u16 * 0x8;
+        self.push_call(self.regs.pc.wrapping_add(1), target);
+
+        target
     }
 
     /// Reset IME to `0`
@@ -121,16 +146,28 @@ impl<T: Memory> Cpu<T> {
 #[cfg(test)]
 mod tests {
     use crate::{
-        cpu::Cpu,
-        memory::{FlatMemory, Memory},
+        cpu::{Access, Comparison, Cpu, CpuError, CpuEvent, CpuStatus, Interrupt, WatchHit, Watchpoint},
+        memory::{mbc::{MbcSelector, RamSize}, FlatMemory, Memory, Mmu},
         ppu::Ppu,
+        Button, Model,
     };
 
     fn init() -> Cpu<FlatMemory> {
         let mmu = FlatMemory::new();
-        let ppu = Ppu::new();
+        let ppu = Ppu::new(Model::Dmg);
 
-        Cpu::new(mmu, ppu, false, true)
+        Cpu::new(mmu, ppu, Model::Dmg, false, true)
+    }
+
+    /// A cartridge system running `program` from 0100, for instructions that touch IO
+    fn init_rom(model: Model, program: &[u8]) -> Cpu<Mmu> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+
+        let mut mmu = Mmu::new(MbcSelector::NoMbc(RamSize::Zero, false), model);
+        mmu.load_rom(&rom);
+
+        Cpu::new(mmu, Ppu::new(model), model, false, true)
     }
 
     #[test]
@@ -219,4 +256,247 @@ mod tests {
         cpu.step();
         assert_eq!(cpu.regs.b, 0b0101_1111);
     }
+
+    #[test]
+    fn illegal_opcode() {
+        let mut cpu = init();
+        cpu.memory.splice(0x0100, &[0xD3, 0x00]);
+
+        let result = cpu.step();
+        assert!(matches!(result, Err(CpuError::IllegalOpcode { pc: 0x0100, opcode: 0xD3 })));
+
+        // stays locked up instead of carrying on to the next instruction
+        assert!(matches!(cpu.step(), Ok(CpuStatus::Locked)));
+        assert_eq!(cpu.regs.pc, 0x0100);
+    }
+
+    #[test]
+    fn halt_bug() {
+        let mut cpu = init();
+        cpu.regs.ime = false;
+        cpu.regs.a = 0;
+        cpu.memory.set(0xFFFF, 0x01);
+        cpu.memory.set(0xFF0F, 0x01);
+        cpu.memory.splice(0x0100, &[0x76, 0x3C, 0x00]);
+
+        // an interrupt is pending, so HALT falls through and INC A runs twice
+        cpu.step().unwrap();
+        assert!(!cpu.halted);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs.a, 2);
+        assert_eq!(cpu.regs.pc, 0x0102);
+    }
+
+    #[test]
+    fn halt_wake() {
+        for ime in [false, true] {
+            let mut cpu = init();
+            cpu.regs.ime = ime;
+            cpu.memory.set(0xFFFF, 0x01);
+            cpu.memory.set(0xFF0F, 0x00);
+            cpu.memory.splice(0x0100, &[0x76, 0x00]);
+
+            cpu.step().unwrap();
+            assert!(matches!(cpu.step(), Ok(CpuStatus::Halt)));
+            assert!(cpu.halted);
+
+            // the cycle spent halted and the cycle waking up, then the 5 cycle dispatch with IME set, or
+            // execution continuing after the HALT without jumping to the handler
+            cpu.memory.set(0xFF0F, 0x01);
+            let tick = cpu.tick;
+            assert!(matches!(cpu.step(), Ok(CpuStatus::Halt)));
+            assert!(!cpu.halted);
+
+            if ime {
+                assert_eq!(cpu.tick - tick, 4 * 7);
+                assert_eq!(cpu.regs.pc, 0x0040);
+                assert_eq!(cpu.memory.load(0xFF0F), Some(0x00));
+            } else {
+                assert_eq!(cpu.tick - tick, 4 * 2);
+                assert_eq!(cpu.regs.pc, 0x0101);
+            }
+        }
+    }
+
+    #[test]
+    fn stop() {
+        // select the buttons, then STOP
+        let mut cpu = init_rom(Model::Dmg, &[0x3E, 0x10, 0xE0, 0x00, 0x10, 0x00, 0x00]);
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+
+        assert!(matches!(cpu.step(), Ok(CpuStatus::Stop)));
+        assert_eq!(cpu.div, 0);
+        assert_eq!(cpu.regs.pc, 0x0106);
+
+        // the dpad isn't selected, so it can't wake the system
+        *cpu.host_input.get_mut(Button::Right) = true;
+        assert!(matches!(cpu.step(), Ok(CpuStatus::Stop)));
+
+        *cpu.host_input.get_mut(Button::Start) = true;
+        assert!(matches!(cpu.step(), Ok(CpuStatus::Run(_))));
+        assert_eq!(cpu.regs.pc, 0x0107);
+    }
+
+    #[test]
+    fn speed_switch() {
+        // arm KEY1, then STOP
+        let program = &[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0xF0, 0x4D];
+
+        let mut cpu = init_rom(Model::Cgb, program);
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+
+        assert!(cpu.double_speed);
+        assert!(!cpu.stop);
+        cpu.step().unwrap();
+        assert_eq!(cpu.regs.a, 0xFE);
+
+        // DMG doesn't have KEY1, so STOP just stops
+        let mut cpu = init_rom(Model::Dmg, program);
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+
+        assert!(!cpu.double_speed);
+        assert!(cpu.stop);
+    }
+
+    #[test]
+    fn interrupt_request_writes() {
+        for (event, hit) in [(Interrupt::Serial, true), (Interrupt::Timer, false)] {
+            // Timer is already requested, so writing $0C to IF only requests Serial
+            let mut cpu = init();
+            cpu.regs.ime = false;
+            cpu.memory.set(0xFF0F, 0x04);
+            cpu.memory.splice(0x0100, &[0x3E, 0x0C, 0xE0, 0x0F]);
+            cpu.breakpoint_controls.set(CpuEvent::InterruptRequest(event));
+
+            cpu.step().unwrap();
+            let status = cpu.step();
+
+            assert_eq!(
+                matches!(status, Ok(CpuStatus::Break(_, CpuEvent::InterruptRequest(i))) if i == event),
+                hit,
+            );
+            assert_eq!(cpu.memory.load(0xFF0F), Some(0x0C));
+        }
+    }
+
+    #[test]
+    fn dmg_stat_write() {
+        // clear IF, then write STAT and read IF back, with the LCD on and off
+        for (lcdc, if_reg) in [(0x91, 0x02), (0x00, 0x00)] {
+            let program = &[0xAF, 0xE0, 0x0F, 0x3E, lcdc, 0xE0, 0x40, 0x3E, 0x08, 0xE0, 0x41, 0xF0, 0x0F];
+            let mut cpu = init_rom(Model::Dmg, program);
+            cpu.memory.set(0xFFFF, 0x00);
+
+            for _ in 0..7 {
+                cpu.step().unwrap();
+            }
+            assert_eq!(cpu.regs.a & 0x1F, if_reg);
+        }
+    }
+
+    #[test]
+    fn interrupt_breakpoints() {
+        for event in [
+            CpuEvent::InterruptRequest(Interrupt::Timer),
+            CpuEvent::Interrupt(Interrupt::Timer),
+        ] {
+            let mut cpu = init();
+            cpu.regs.ime = true;
+            cpu.memory.set(0xFFFF, 0x04);
+            cpu.memory.set(0xFF0F, 0x00);
+            cpu.memory.splice(0x0100, &[0x00; 0x10]);
+
+            // TIMA overflows after 16 T-cycles
+            cpu.memory.set(0xFF05, 0xFF);
+            cpu.memory.set(0xFF07, 0x05);
+            cpu.breakpoint_controls.set(event);
+
+            let hit = (0..8).find_map(|_| match cpu.step() {
+                Ok(CpuStatus::Break(_, hit)) => Some(hit),
+                _ => None,
+            });
+
+            assert_eq!(hit, Some(event));
+            // both happen in the same step, and the handler is entered before it returns
+            assert_eq!(cpu.regs.pc, 0x0050);
+        }
+    }
+
+    #[test]
+    fn conditional_breakpoint() {
+        // LD A, 4, then loop on JR -2 at 0103
+        let mut cpu = init_rom(Model::Dmg, &[0x3E, 0x04, 0x00, 0x18, 0xFE]);
+
+        let id = cpu.breakpoint_controls.set_condition("pc == $0103 && a > 3", None).unwrap();
+        cpu.step().unwrap();
+        let Ok(CpuStatus::Break(_, hit)) = cpu.step() else { panic!("condition wasn't hit") };
+        assert_eq!(hit, CpuEvent::Condition(id));
+
+        // only checked when the JR runs, so the third time is the second loop
+        cpu.breakpoint_controls.unset_condition(id);
+        let id = cpu
+            .breakpoint_controls
+            .set_condition("hits == 3 && [pc] == $18", Some(CpuEvent::OpCode(0x18)))
+            .unwrap();
+
+        let hit = (0..4).position(|_| matches!(cpu.step(), Ok(CpuStatus::Break(..))));
+        assert_eq!(hit, Some(2));
+        assert_eq!(cpu.breakpoint_controls.conditions[0].id, id);
+        assert!(cpu.breakpoint_controls.set_condition("pc ==", None).is_err());
+    }
+
+    #[test]
+    fn watchpoints() {
+        // LD HL, $C000, then LD (HL), $12 twice and LD A, (HL)
+        let mut cpu = init_rom(Model::Dmg, &[0x21, 0x00, 0xC0, 0x36, 0x12, 0x36, 0x12, 0x7E]);
+
+        let wram = Watchpoint::new(0xC000..=0xDFFF, Access::Write).changes_only();
+        let id = cpu.breakpoint_controls.set_watchpoint(wram);
+        assert!(matches!(cpu.step(), Ok(CpuStatus::Run(_))));
+
+        let Ok(CpuStatus::Break(_, CpuEvent::Watch(hit))) = cpu.step() else { panic!("write wasn't hit") };
+        let old = hit.old;
+        assert_eq!(
+            hit,
+            WatchHit { id, addr: 0xC000, access: Access::Write, value: 0x12, old, pc: 0x0103 }
+        );
+        assert_ne!(old, 0x12);
+
+        // the value doesn't change the second time
+        assert!(matches!(cpu.step(), Ok(CpuStatus::Run(_))));
+
+        cpu.breakpoint_controls.unset_watchpoint(id);
+        let read = Watchpoint::new(0xC000..=0xDFFF, Access::Read).with_value(Comparison::Eq(0x12));
+        let id = cpu.breakpoint_controls.set_watchpoint(read);
+
+        let Ok(CpuStatus::Break(_, CpuEvent::Watch(hit))) = cpu.step() else { panic!("read wasn't hit") };
+        assert_eq!(
+            hit,
+            WatchHit { id, addr: 0xC000, access: Access::Read, value: 0x12, old: 0x12, pc: 0x0107 }
+        );
+    }
+
+    #[test]
+    fn watchpoint_fetches() {
+        // LD A, $3E and JP $0105 run through the watched range without reading it, then LD A, ($0101) does
+        let mut cpu = init_rom(Model::Dmg, &[0x3E, 0x3E, 0xC3, 0x05, 0x01, 0xFA, 0x01, 0x01]);
+        let id = cpu.breakpoint_controls.set_watchpoint(Watchpoint::new(0x0100..=0x0107, Access::Read));
+
+        for _ in 0..2 {
+            assert!(matches!(cpu.step(), Ok(CpuStatus::Run(_))));
+        }
+
+        let Ok(CpuStatus::Break(_, CpuEvent::Watch(hit))) = cpu.step() else { panic!("read wasn't hit") };
+        assert_eq!(
+            hit,
+            WatchHit { id, addr: 0x0101, access: Access::Read, value: 0x3E, old: 0x3E, pc: 0x0105 }
+        );
+    }
 }
@@ -0,0 +1,337 @@
+use std::fmt::{self, Display};
+
+use super::*;
+
+/// Operand values read from after an opcode, used to fill in the operands when formatting
+#[derive(Clone, Copy)]
+struct Operands<'a> {
+    /// Address of the opcode, so relative jumps can be resolved
+    addr: u16,
+    bytes: &'a [u8],
+}
+
+impl Operands<'_> {
+    fn byte(&self) -> u8 {
+        self.bytes.first().copied().unwrap_or(0)
+    }
+
+    fn word(&self) -> u16 {
+        u16::from_le_bytes([self.byte(), self.bytes.get(1).copied().unwrap_or(0)])
+    }
+}
+
+/// Formats `n8`, or the immediate byte if the operands are known
+fn n8(ops: Option<Operands>) -> String {
+    ops.map_or("n8".to_string(), |ops| format!("${:02X}", ops.byte()))
+}
+
+/// Formats `placeholder`, or the immediate word if the operands are known
+fn n16(ops: Option<Operands>, placeholder: &str) -> String {
+    ops.map_or(placeholder.to_string(), |ops| format!("${:04X}", ops.word()))
+}
+
+/// Formats `e8`, or the signed immediate byte if the operands are known
+fn e8(ops: Option<Operands>) -> String {
+    ops.map_or("e8".to_string(), |ops| match ops.byte() as i8 {
+        offset if offset < 0 => format!("-${:02X}", offset.unsigned_abs()),
+        offset => format!("${offset:02X}"),
+    })
+}
+
+impl Display for ArithmeticTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ArithmeticTarget::A => "a",
+            ArithmeticTarget::B => "b",
+            ArithmeticTarget::C => "c",
+            ArithmeticTarget::D => "d",
+            ArithmeticTarget::E => "e",
+            ArithmeticTarget::H => "h",
+            ArithmeticTarget::L => "l",
+            ArithmeticTarget::HL => "[hl]",
+            ArithmeticTarget::Immediate => "n8",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+impl Display for WordArithmeticTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            WordArithmeticTarget::BC => "bc",
+            WordArithmeticTarget::DE => "de",
+            WordArithmeticTarget::HL => "hl",
+            WordArithmeticTarget::SP => "sp",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+impl Display for ByteTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ByteTarget::A => "a",
+            ByteTarget::B => "b",
+            ByteTarget::C => "c",
+            ByteTarget::D => "d",
+            ByteTarget::E => "e",
+            ByteTarget::H => "h",
+            ByteTarget::L => "l",
+            ByteTarget::HL => "[hl]",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+impl Display for StackTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            StackTarget::BC => "bc",
+            StackTarget::DE => "de",
+            StackTarget::HL => "hl",
+            StackTarget::AF => "af",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+impl JumpTest {
+    /// The condition followed by a comma, or nothing if the jump is unconditional
+    fn prefix(&self) -> &'static str {
+        match self {
+            JumpTest::NotZero => "nz, ",
+            JumpTest::Zero => "z, ",
+            JumpTest::NotCarry => "nc, ",
+            JumpTest::Carry => "c, ",
+            JumpTest::Always => "",
+        }
+    }
+
+    /// The condition on its own, for RET
+    fn name(&self) -> &'static str {
+        self.prefix().trim_end_matches(", ")
+    }
+}
+
+impl Instruction {
+    /// Length of the instruction in bytes, including the CB prefix and any operands
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::ADD(target)
+            | Instruction::ADC(target)
+            | Instruction::SUB(target)
+            | Instruction::SBC(target)
+            | Instruction::AND(target)
+            | Instruction::OR(target)
+            | Instruction::XOR(target)
+            | Instruction::CP(target) => match target {
+                ArithmeticTarget::Immediate => 2,
+                _ => 1,
+            },
+            Instruction::BIT(..)
+            | Instruction::RES(..)
+            | Instruction::SET(..)
+            | Instruction::SRL(_)
+            | Instruction::RR(_)
+            | Instruction::RL(_)
+            | Instruction::RRC(_)
+            | Instruction::RLC(_)
+            | Instruction::SRA(_)
+            | Instruction::SLA(_)
+            | Instruction::SWAP(_) => 2,
+            Instruction::JP(_) | Instruction::CALL(_) => 3,
+            Instruction::JR(_) | Instruction::STOP | Instruction::ADDSP => 2,
+            Instruction::LD(load) => match load {
+                LoadType::Byte(_, ByteSource::Immediate) => 2,
+                LoadType::Byte(..) => 1,
+                LoadType::Word(WordTarget::HLFromSP) => 2,
+                LoadType::Word(WordTarget::SPFromHL) => 1,
+                LoadType::Word(_) => 3,
+                LoadType::IndirectIntoA(AddressSource::Immediate)
+                | LoadType::IndirectFromA(AddressSource::Immediate) => 3,
+                LoadType::IndirectIntoA(_) | LoadType::IndirectFromA(_) => 1,
+                LoadType::ByteAddressIntoA(ByteAddressSource::Immediate)
+                | LoadType::ByteAddressFromA(ByteAddressSource::Immediate) => 2,
+                LoadType::ByteAddressIntoA(_) | LoadType::ByteAddressFromA(_) => 1,
+                LoadType::SPOffset => 2,
+            },
+            _ => 1,
+        }
+    }
+
+    /// Formats the instruction as SM83 assembly, with the operands filled in
+    ///
+    /// `addr` is where the opcode is, used to resolve relative jumps, and `operands` are the bytes after the opcode
+    /// (after the CB prefix for prefixed instructions), missing bytes are read as `0`
+    pub fn to_asm(&self, addr: u16, operands: &[u8]) -> String {
+        self.format(Some(Operands { addr, bytes: operands }))
+    }
+
+    /// Formats the instruction, using placeholders like `n8` and `a16` for its operands if they're not known
+    fn format(&self, ops: Option<Operands>) -> String {
+        let arith = |target: &ArithmeticTarget| match target {
+            ArithmeticTarget::Immediate => n8(ops),
+            _ => target.to_string(),
+        };
+
+        match self {
+            Instruction::ADD(target) => format!("add a, {}", arith(target)),
+            Instruction::ADC(target) => format!("adc a, {}", arith(target)),
+            Instruction::SUB(target) => format!("sub a, {}", arith(target)),
+            Instruction::SBC(target) => format!("sbc a, {}", arith(target)),
+            Instruction::AND(target) => format!("and a, {}", arith(target)),
+            Instruction::OR(target) => format!("or a, {}", arith(target)),
+            Instruction::XOR(target) => format!("xor a, {}", arith(target)),
+            Instruction::CP(target) => format!("cp a, {}", arith(target)),
+            Instruction::INC(target) => format!("inc {target}"),
+            Instruction::DEC(target) => format!("dec {target}"),
+            Instruction::CCF => "ccf".to_string(),
+            Instruction::SCF => "scf".to_string(),
+            Instruction::RRA => "rra".to_string(),
+            Instruction::RLA => "rla".to_string(),
+            Instruction::RRCA => "rrca".to_string(),
+            Instruction::RLCA => "rlca".to_string(),
+            Instruction::CPL => "cpl".to_string(),
+            Instruction::BIT(target, bit) => format!("bit {bit}, {target}"),
+            Instruction::RES(target, bit) => format!("res {bit}, {target}"),
+            Instruction::SET(target, bit) => format!("set {bit}, {target}"),
+            Instruction::SRL(target) => format!("srl {target}"),
+            Instruction::RR(target) => format!("rr {target}"),
+            Instruction::RL(target) => format!("rl {target}"),
+            Instruction::RRC(target) => format!("rrc {target}"),
+            Instruction::RLC(target) => format!("rlc {target}"),
+            Instruction::SRA(target) => format!("sra {target}"),
+            Instruction::SLA(target) => format!("sla {target}"),
+            Instruction::SWAP(target) => format!("swap {target}"),
+            Instruction::JP(test) => format!("jp {}{}", test.prefix(), n16(ops, "a16")),
+            Instruction::JR(test) => {
+                let target = ops.map_or("e8".to_string(), |ops| {
+                    let target = ops.addr.wrapping_add(2).wrapping_add(ops.byte() as i8 as u16);
+                    format!("${target:04X}")
+                });
+
+                format!("jr {}{target}", test.prefix())
+            }
+            Instruction::JPHL => "jp hl".to_string(),
+            Instruction::LD(load) => match load {
+                LoadType::Byte(target, source) => {
+                    let source = match source {
+                        ByteSource::A => "a".to_string(),
+                        ByteSource::B => "b".to_string(),
+                        ByteSource::C => "c".to_string(),
+                        ByteSource::D => "d".to_string(),
+                        ByteSource::E => "e".to_string(),
+                        ByteSource::H => "h".to_string(),
+                        ByteSource::L => "l".to_string(),
+                        ByteSource::HL => "[hl]".to_string(),
+                        ByteSource::Immediate => n8(ops),
+                    };
+
+                    format!("ld {target}, {source}")
+                }
+                LoadType::Word(target) => match target {
+                    WordTarget::BC => format!("ld bc, {}", n16(ops, "n16")),
+                    WordTarget::DE => format!("ld de, {}", n16(ops, "n16")),
+                    WordTarget::HL => format!("ld hl, {}", n16(ops, "n16")),
+                    WordTarget::SP => format!("ld sp, {}", n16(ops, "n16")),
+                    WordTarget::HLFromSP => Self::ld_hl_sp(ops),
+                    WordTarget::SPFromHL => "ld sp, hl".to_string(),
+                    WordTarget::Immediate => format!("ld [{}], sp", n16(ops, "a16")),
+                },
+                LoadType::IndirectIntoA(source) => format!("ld a, [{}]", Self::address(source, ops)),
+                LoadType::IndirectFromA(source) => format!("ld [{}], a", Self::address(source, ops)),
+                LoadType::ByteAddressIntoA(source) => format!("ldh a, [{}]", Self::high_address(source, ops)),
+                LoadType::ByteAddressFromA(source) => format!("ldh [{}], a", Self::high_address(source, ops)),
+                LoadType::SPOffset => Self::ld_hl_sp(ops),
+            },
+            Instruction::PUSH(target) => format!("push {target}"),
+            Instruction::POP(target) => format!("pop {target}"),
+            Instruction::STOP => "stop".to_string(),
+            Instruction::HALT => "halt".to_string(),
+            Instruction::DAA => "daa".to_string(),
+            Instruction::NOP => "nop".to_string(),
+            Instruction::RET(JumpTest::Always) => "ret".to_string(),
+            Instruction::RET(test) => format!("ret {}", test.name()),
+            Instruction::RETI => "reti".to_string(),
+            Instruction::CALL(test) => format!("call {}{}", test.prefix(), n16(ops, "a16")),
+            Instruction::RST(n) => format!("rst ${:02X}", *n as u16 * 0x08),
+            Instruction::DI => "di".to_string(),
+            Instruction::EI => "ei".to_string(),
+            Instruction::ADDHL(target) => format!("add hl, {target}"),
+            Instruction::INCW(target) => format!("inc {target}"),
+            Instruction::DECW(target) => format!("dec {target}"),
+            Instruction::ADDSP => format!("add sp, {}", e8(ops)),
+        }
+    }
+
+    fn ld_hl_sp(ops: Option<Operands>) -> String {
+        match e8(ops) {
+            offset if offset.starts_with('-') => format!("ld hl, sp{offset}"),
+            offset => format!("ld hl, sp+{offset}"),
+        }
+    }
+
+    fn address(source: &AddressSource, ops: Option<Operands>) -> String {
+        match source {
+            AddressSource::BC => "bc".to_string(),
+            AddressSource::DE => "de".to_string(),
+            AddressSource::HLUp => "hl+".to_string(),
+            AddressSource::HLDown => "hl-".to_string(),
+            AddressSource::Immediate => n16(ops, "a16"),
+        }
+    }
+
+    fn high_address(source: &ByteAddressSource, ops: Option<Operands>) -> String {
+        match source {
+            ByteAddressSource::Immediate => {
+                ops.map_or("a8".to_string(), |ops| format!("${:04X}", 0xFF00 | ops.byte() as u16))
+            }
+            ByteAddressSource::C => "c".to_string(),
+        }
+    }
+}
+
+/// Formats the instruction as SM83 assembly, with placeholders for the operands, such as `ld a, n8`
+///
+/// Use `Instruction::to_asm()` or the disassembler to fill them in
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.format(None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::Instruction;
+
+    #[test]
+    fn display() {
+        let asm = |opcode: u8| Instruction::from_byte(false, opcode).unwrap().to_string();
+
+        assert_eq!(asm(0x3E), "ld a, n8");
+        assert_eq!(asm(0x20), "jr nz, e8");
+        assert_eq!(asm(0x77), "ld [hl], a");
+        assert_eq!(asm(0xC9), "ret");
+        assert_eq!(asm(0xD8), "ret c");
+        assert_eq!(asm(0xE0), "ldh [a8], a");
+        assert_eq!(asm(0xEF), "rst $28");
+        assert_eq!(Instruction::from_byte(true, 0x7C).unwrap().to_string(), "bit 7, h");
+    }
+
+    #[test]
+    fn to_asm() {
+        let asm = |opcode: u8, ops: &[u8]| Instruction::from_byte(false, opcode).unwrap().to_asm(0x0150, ops);
+
+        assert_eq!(asm(0x3E, &[0x3C]), "ld a, $3C");
+        assert_eq!(asm(0x20, &[0xFE]), "jr nz, $0150");
+        assert_eq!(asm(0xCD, &[0x34, 0x12]), "call $1234");
+        assert_eq!(asm(0xF0, &[0x44]), "ldh a, [$FF44]");
+        assert_eq!(asm(0xF8, &[0xFF]), "ld hl, sp-$01");
+        assert_eq!(asm(0xE8, &[0x10]), "add sp, $10");
+        assert_eq!(asm(0x08, &[0x00, 0xC0]), "ld [$C000], sp");
+    }
+}
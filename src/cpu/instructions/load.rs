@@ -171,15 +171,16 @@ impl<T: Memory> Cpu<T> {
 mod tests {
     use crate::{
         cpu::Cpu,
-        memory::{mbc::MbcSelector, Memory, Mmu},
+        memory::{mbc::{MbcSelector, RamSize}, Memory, Mmu},
         ppu::Ppu,
+        Model,
     };
 
     fn init() -> Cpu<Mmu> {
-        let mmu = Mmu::new(MbcSelector::NoMbc);
-        let ppu = Ppu::new();
+        let mmu = Mmu::new(MbcSelector::NoMbc(RamSize::Two, false), Model::Dmg);
+        let ppu = Ppu::new(Model::Dmg);
 
-        Cpu::new(mmu, ppu, false, true)
+        Cpu::new(mmu, ppu, Model::Dmg, false, true)
     }
 
     #[test]
@@ -38,16 +38,17 @@ impl<T: Memory> Cpu<T> {
 #[cfg(test)]
 mod tests {
     use crate::{
-        cpu::Cpu,
+        cpu::{Comparison, Cpu, CpuEvent, CpuReg, CpuStatus},
         memory::{FlatMemory, Memory},
         ppu::Ppu,
+        Model,
     };
 
     fn init() -> Cpu<FlatMemory> {
         let mmu = FlatMemory::new();
-        let ppu = Ppu::new();
+        let ppu = Ppu::new(Model::Dmg);
 
-        Cpu::new(mmu, ppu, false, true)
+        Cpu::new(mmu, ppu, Model::Dmg, false, true)
     }
 
     #[test]
@@ -94,4 +95,33 @@ mod tests {
         cpu.step();
         assert_eq!(cpu.regs.get_af(), 0x1230);
     }
+
+    #[test]
+    fn sp_breakpoint() {
+        let mut cpu = init();
+        // PUSH BC four times, then POP DE
+        cpu.memory.splice(cpu.regs.pc, &[0xC5, 0xC5, 0xC5, 0xC5, 0xD1]);
+        cpu.regs.sp = 0xC106;
+
+        let breakpoint = CpuEvent::RegValue(CpuReg::SP, Comparison::Lt(0xC100));
+        cpu.breakpoint_controls.set(breakpoint);
+
+        for _ in 0..3 {
+            assert!(matches!(cpu.step(), Ok(CpuStatus::Run(_))));
+        }
+        assert_eq!(cpu.regs.sp, 0xC100);
+
+        let Ok(CpuStatus::Break(_, hit)) = cpu.step() else { panic!("SP breakpoint wasn't hit") };
+        assert_eq!(hit, breakpoint);
+
+        // E is covered by its own event, and through DE
+        cpu.breakpoint_controls.unset(breakpoint);
+        cpu.breakpoint_controls.set(CpuEvent::Reg(CpuReg::E));
+        cpu.regs.set_bc(0x1234);
+        cpu.memory.splice(0xC0FE, &[0x34, 0x12]);
+
+        let Ok(CpuStatus::Break(_, hit)) = cpu.step() else { panic!("E breakpoint wasn't hit") };
+        assert_eq!(hit, CpuEvent::Reg(CpuReg::E));
+        assert_eq!(cpu.regs.get_de(), 0x1234);
+    }
 }
@@ -0,0 +1,39 @@
+/// An interrupt source, each one has a bit in IE and IF
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Interrupt {
+    VBlank,
+    Stat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl Interrupt {
+    /// Every interrupt, from highest to lowest priority
+    pub const ALL: [Interrupt; 5] = [
+        Interrupt::VBlank,
+        Interrupt::Stat,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::Joypad,
+    ];
+
+    /// The interrupt's bit in IE and IF
+    pub fn mask(&self) -> u8 {
+        1 << *self as u8
+    }
+
+    /// Address of the interrupt's handler
+    pub fn vector(&self) -> u16 {
+        0x40 + 0x08 * *self as u16
+    }
+
+    /// Returns the interrupt with bit `bit` in IE and IF
+    ///
+    /// ### Return Variants
+    /// - `Some(interrupt)` if `bit` is `0 - 4`
+    /// - `None` otherwise
+    pub fn from_bit(bit: u8) -> Option<Self> {
+        Self::ALL.get(bit as usize).copied()
+    }
+}
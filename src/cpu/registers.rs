@@ -1,5 +1,7 @@
 use std::fmt::Display;
 
+use crate::Model;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CpuFlag {
     Zero,
@@ -11,11 +13,39 @@ pub enum CpuFlag {
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CpuReg {
     A,
+    F,
     B,
     C,
     D,
+    E,
     H,
     L,
+    SP,
+    PC,
+    AF,
+    BC,
+    DE,
+    HL,
+}
+
+impl CpuReg {
+    /// Every register, 8 bit registers first
+    pub const ALL: [CpuReg; 14] = [
+        CpuReg::A,
+        CpuReg::F,
+        CpuReg::B,
+        CpuReg::C,
+        CpuReg::D,
+        CpuReg::E,
+        CpuReg::H,
+        CpuReg::L,
+        CpuReg::SP,
+        CpuReg::PC,
+        CpuReg::AF,
+        CpuReg::BC,
+        CpuReg::DE,
+        CpuReg::HL,
+    ];
 }
 
 /// Flag register state
@@ -111,8 +141,9 @@ impl Display for Registers {
 }
 
 impl Registers {
+    /// Register values after the DMG boot ROM has run, see `Registers::post_boot()` for other models
     pub fn new() -> Self {
-        // init values from mooneye's test roms (misc/boot_regs-cgb)
+        // init values from mooneye's test roms (misc/boot_regs-dmgABC)
         Self {
             a: 0x01,
             f: Flags::new(),
@@ -128,6 +159,68 @@ impl Registers {
         }
     }
 
+    /// Register values once `model`'s boot ROM hands over to the cartridge at 0100
+    pub fn post_boot(model: Model) -> Self {
+        let (af, bc, de, hl) = match model {
+            Model::Dmg0 => (0x0100, 0xFF13, 0x00C1, 0x8403),
+            Model::Dmg => (0x01B0, 0x0013, 0x00D8, 0x014D),
+            Model::Mgb => (0xFFB0, 0x0013, 0x00D8, 0x014D),
+            Model::Sgb => (0x0100, 0x0014, 0x0000, 0xC060),
+            Model::Sgb2 => (0xFF00, 0x0014, 0x0000, 0xC060),
+            Model::Cgb => (0x1180, 0x0000, 0xFF56, 0x000D),
+            Model::Agb => (0x1100, 0x0100, 0xFF56, 0x000D),
+        };
+
+        let mut out = Self::new();
+        out.set_af(af);
+        out.set_bc(bc);
+        out.set_de(de);
+        out.set_hl(hl);
+        out
+    }
+
+    /// Register values at power on, before a boot ROM has run
+    pub fn power_on() -> Self {
+        Self {
+            a: 0x00,
+            f: Flags {
+                zero: false,
+                subtract: false,
+                half_carry: false,
+                carry: false,
+            },
+            b: 0x00,
+            c: 0x00,
+            d: 0x00,
+            e: 0x00,
+            h: 0x00,
+            l: 0x00,
+            sp: 0x0000,
+            pc: 0x0000,
+            ime: false,
+        }
+    }
+
+    /// Gets the value of `reg`, 8 bit registers are zero extended
+    pub fn get(&self, reg: CpuReg) -> u16 {
+        match reg {
+            CpuReg::A => self.a as u16,
+            CpuReg::F => self.f.as_byte() as u16,
+            CpuReg::B => self.b as u16,
+            CpuReg::C => self.c as u16,
+            CpuReg::D => self.d as u16,
+            CpuReg::E => self.e as u16,
+            CpuReg::H => self.h as u16,
+            CpuReg::L => self.l as u16,
+            CpuReg::SP => self.sp,
+            CpuReg::PC => self.pc,
+            CpuReg::AF => self.get_af(),
+            CpuReg::BC => self.get_bc(),
+            CpuReg::DE => self.get_de(),
+            CpuReg::HL => self.get_hl(),
+        }
+    }
+
     /// Gets the word stored in the `BC` register pair
     pub fn get_bc(&self) -> u16 {
         ((self.b as u16) << 8) | self.c as u16
@@ -0,0 +1,183 @@
+use std::io::{self, Write};
+
+use super::Expr;
+
+/// How each instruction is written to the trace
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// The Gameboy Doctor format, `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+    ///
+    /// Use `Trace::with_doctor_ly()` as well to match Gameboy Doctor's reference logs
+    #[default]
+    Doctor,
+    /// The Gameboy Doctor format, followed by the T-cycle count, LY and the ROM bank mapped into 4000-7FFF, like
+    /// `CY:1234 LY:90 BANK:01`
+    Detailed,
+}
+
+/// Writes the CPU state before every instruction, for comparing runs against other emulators
+pub struct Trace {
+    pub format: TraceFormat,
+    pub output: Box<dyn Write + Send>,
+    /// Tracing starts once this is true before an instruction, or straight away if it's `None`
+    pub start: Option<Expr>,
+    /// Tracing stops for good once this is true before an instruction
+    pub stop: Option<Expr>,
+    /// Number of lines written, available to the conditions as `hits`
+    pub lines: u32,
+    /// Whether LY always reads 0x90 until the trace is stopped, which Gameboy Doctor's reference logs expect
+    ///
+    /// This changes what the running program sees, so it's off unless asked for
+    pub stub_ly: bool,
+    pub(crate) started: bool,
+    pub(crate) stopped: bool,
+    /// The first error writing to `output`, which also stops tracing
+    pub error: Option<io::Error>,
+}
+
+impl Trace {
+    /// Creates a trace writing every instruction to `output`
+    pub fn new(format: TraceFormat, output: Box<dyn Write + Send>) -> Self {
+        Self {
+            format,
+            output,
+            start: None,
+            stop: None,
+            lines: 0,
+            stub_ly: false,
+            started: false,
+            stopped: false,
+            error: None,
+        }
+    }
+
+    /// Only start tracing once `start` is true, such as `pc == $0150`
+    pub fn starting_when(mut self, start: Expr) -> Self {
+        self.start = Some(start);
+        self
+    }
+
+    /// Stop tracing for good once `stop` is true
+    pub fn stopping_when(mut self, stop: Expr) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Have LY always read 0x90 while tracing, to compare against Gameboy Doctor's reference logs
+    pub fn with_doctor_ly(mut self) -> Self {
+        self.stub_ly = true;
+        self
+    }
+
+    /// Returns whether the stop condition was met, or writing failed
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Returns whether LY should read 0x90 right now
+    pub(crate) fn stubs_ly(&self) -> bool {
+        self.stub_ly && !self.stopped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    use crate::{
+        memory::{
+            mbc::{MbcSelector, RamSize},
+            FlatMemory,
+        },
+        Gbc, Model,
+    };
+
+    use super::{Trace, TraceFormat};
+
+    /// Output that can still be read after being handed to a trace
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap().lines().map(str::to_string).collect()
+        }
+    }
+
+    fn init(trace: impl FnOnce(Box<Shared>) -> Trace) -> (Gbc<FlatMemory>, Shared) {
+        let mut gbc = Gbc::new_flat(Model::Dmg, false, true);
+        gbc.load_program(&crate::gbasm!(ld a, 5 / ld b, 3 / add b / done: jr done));
+
+        let output = Shared::default();
+        gbc.set_trace(Some(trace(Box::new(output.clone()))));
+        (gbc, output)
+    }
+
+    #[test]
+    fn doctor() {
+        let (mut gbc, output) = init(|out| Trace::new(TraceFormat::Doctor, out));
+        assert!(gbc.step().0.is_ok());
+        assert!(gbc.step().0.is_ok());
+
+        assert_eq!(
+            output.lines(),
+            vec![
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:3E,05,06,03",
+                "A:05 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102 PCMEM:06,03,80,18",
+            ]
+        );
+    }
+
+    #[test]
+    fn doctor_ly() {
+        let traces: [fn(Box<Shared>) -> Trace; 3] = [
+            |out| Trace::new(TraceFormat::Doctor, out),
+            |out| Trace::new(TraceFormat::Doctor, out).with_doctor_ly(),
+            |out| Trace::new(TraceFormat::Detailed, out).with_doctor_ly(),
+        ];
+
+        for (trace, ly) in traces.into_iter().zip([0x00, 0x90, 0x90]) {
+            let mut rom = vec![0; 0x8000];
+            rom[0x0100..0x0102].copy_from_slice(&[0xF0, 0x44]);
+
+            let mut gbc = Gbc::new(MbcSelector::NoMbc(RamSize::Zero, false), Model::Dmg, false, true);
+            gbc.load_rom(&rom);
+            gbc.set_trace(Some(trace(Box::default())));
+
+            assert!(gbc.step().0.is_ok());
+            assert_eq!(gbc.cpu.regs.a, ly);
+        }
+    }
+
+    #[test]
+    fn conditions() {
+        let (mut gbc, output) = init(|out| {
+            Trace::new(TraceFormat::Detailed, out)
+                .starting_when("pc == $0104".parse().unwrap())
+                .stopping_when("hits == 2".parse().unwrap())
+        });
+
+        for _ in 0..6 {
+            assert!(gbc.step().0.is_ok());
+        }
+
+        let lines = output.lines();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("A:05 F:B0 B:03 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0104 PCMEM:80,18,FE,00 CY:"));
+        assert!(lines[1].contains("PC:0105") && lines[1].ends_with("BANK:01"));
+        assert!(gbc.cpu.trace.as_ref().unwrap().is_stopped());
+    }
+}
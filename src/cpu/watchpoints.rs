@@ -0,0 +1,105 @@
+use std::ops::RangeInclusive;
+
+use super::Comparison;
+
+/// The kind of memory access a watchpoint triggers on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Access {
+    /// Data reads, fetching opcodes and operands doesn't count
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    /// Returns whether a watchpoint on `self` triggers on an access of kind `access`
+    pub fn covers(&self, access: Access) -> bool {
+        *self == Access::ReadWrite || *self == access
+    }
+}
+
+/// A breakpoint over a range of memory
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub id: usize,
+    pub range: RangeInclusive<u16>,
+    pub access: Access,
+    /// Only trigger when the value read or written satisfies this
+    pub value: Option<Comparison>,
+    /// Only trigger on writes that change the value in memory
+    pub changes_only: bool,
+}
+
+impl Watchpoint {
+    /// Creates a watchpoint over `range` that triggers on any `access`, the id is assigned by
+    /// `Breakpoints::set_watchpoint()`
+    pub fn new(range: RangeInclusive<u16>, access: Access) -> Self {
+        Self {
+            id: 0,
+            range,
+            access,
+            value: None,
+            changes_only: false,
+        }
+    }
+
+    /// Only trigger when the value read or written satisfies `value`
+    pub fn with_value(mut self, value: Comparison) -> Self {
+        self.value = Some(value);
+        self
+    }
+
+    /// Only trigger on writes that change the value in memory
+    pub fn changes_only(mut self) -> Self {
+        self.changes_only = true;
+        self
+    }
+
+    /// Returns whether an access of kind `access` to `addr` triggers the watchpoint
+    ///
+    /// `old` is the value in memory before the access, and `value` is the value read or written
+    pub fn matches(&self, addr: u16, access: Access, value: u8, old: u8) -> bool {
+        self.range.contains(&addr)
+            && self.access.covers(access)
+            && self.value.is_none_or(|cmp| cmp.matches(value as u16))
+            && (!self.changes_only || (access == Access::Write && value != old))
+    }
+}
+
+/// Reported through `CpuEvent::Watch` when a watchpoint triggers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    /// Id of the watchpoint that triggered
+    pub id: usize,
+    pub addr: u16,
+    pub access: Access,
+    /// The value read or written
+    pub value: u8,
+    /// The value in memory before the access, the same as `value` for reads
+    pub old: u8,
+    /// Start of the instruction that made the access
+    pub pc: u16,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::Comparison;
+
+    use super::{Access, Watchpoint};
+
+    #[test]
+    fn matches() {
+        let wram = Watchpoint::new(0xC000..=0xDFFF, Access::Write);
+        assert!(wram.matches(0xC123, Access::Write, 0x00, 0x00));
+        assert!(!wram.matches(0xC123, Access::Read, 0x00, 0x00));
+        assert!(!wram.matches(0xE000, Access::Write, 0x00, 0x00));
+
+        let changes = wram.clone().changes_only();
+        assert!(!changes.matches(0xC123, Access::Write, 0x12, 0x12));
+        assert!(changes.matches(0xC123, Access::Write, 0x13, 0x12));
+
+        let value = Watchpoint::new(0xFF44..=0xFF44, Access::ReadWrite).with_value(Comparison::Ge(0x90));
+        assert!(value.matches(0xFF44, Access::Read, 0x91, 0x91));
+        assert!(!value.matches(0xFF44, Access::Read, 0x10, 0x10));
+    }
+}
@@ -1,32 +1,129 @@
+use std::ops::RangeInclusive;
+
 use crate::{
-    cpu::{Cpu, CpuError, CpuStatus},
-    memory::{mbc::MbcSelector, FlatMemory, Memory, Mmu},
-    ppu::Ppu, Button,
+    cpu::{disassemble, disassemble_range, Cpu, CpuError, CpuStatus, Disassembly, Frame, Instruction, Interrupt, Trace},
+    memory::{
+        mbc::{CameraSource, CartridgeHeader, MbcSelector, RomLoadError, SaveError},
+        self, FlatMemory, Memory, Mmu,
+    },
+    ppu::Ppu, Button, Model,
 };
 
 pub const MBC_ADDR: usize = 0x0147;
 
+/// T-cycles in a frame, used to count frames while the LCD is off
+const FRAME_CYCLES: usize = 70224;
+
+/// Default `Gbc::run_limit`, about 10 seconds of emulated time
+const RUN_LIMIT: usize = FRAME_CYCLES * 600;
+
 pub struct Gbc<T: Memory> {
     pub cpu: Cpu<T>,
+    /// T-cycles `step_over()`, `step_out()` and `run_to()` run for before giving up and returning
+    pub run_limit: usize,
 }
 
 impl Gbc<FlatMemory> {
-    pub fn new_flat(debug: bool, allow_uninit: bool) -> Self {
+    pub fn new_flat(model: Model, debug: bool, allow_uninit: bool) -> Self {
         let memory = FlatMemory::new();
-        let ppu = Ppu::new();
-        let cpu = Cpu::new(memory, ppu, debug, allow_uninit);
+        let ppu = Ppu::new(model);
+        let cpu = Cpu::new(memory, ppu, model, debug, allow_uninit);
+
+        Self { cpu, run_limit: RUN_LIMIT }
+    }
 
-        Self { cpu }
+    /// Copies `program` to 0100, where execution starts, such as one built with `gbasm!`
+    pub fn load_program(&mut self, program: &[u8]) {
+        self.cpu.memory.splice(0x0100, program);
     }
 }
 
 impl Gbc<Mmu> {
-    pub fn new(mbc: MbcSelector, debug: bool, allow_uninit: bool) -> Self {
-        let memory = Mmu::new(mbc);
-        let ppu = Ppu::new();
-        let cpu = Cpu::new(memory, ppu, debug, allow_uninit);
+    /// Builds a system emulating `model`, in the state its boot ROM leaves it in
+    pub fn new(mbc: MbcSelector, model: Model, debug: bool, allow_uninit: bool) -> Self {
+        let memory = Mmu::new(mbc, model);
+        let ppu = Ppu::new(model);
+        let cpu = Cpu::new(memory, ppu, model, debug, allow_uninit);
+
+        Self { cpu, run_limit: RUN_LIMIT }
+    }
+
+    /// Builds a system for the cartridge described by `rom`'s header and loads `rom` into it
+    ///
+    /// ### Return Variants
+    /// - `Err(RomLoadError)` if the header is invalid, describes an unsupported cartridge, or doesn't match the size of `rom`
+    pub fn from_rom(rom: &[u8], model: Model, debug: bool, allow_uninit: bool) -> Result<Self, RomLoadError> {
+        let header = CartridgeHeader::try_parse(rom)?;
+        header.check_image(rom)?;
+
+        let memory = Mmu::try_new(header.try_mbc_selector()?, model)?;
+        let ppu = Ppu::new(model);
+        let mut cpu = Cpu::new(memory, ppu, model, debug, allow_uninit);
+        cpu.try_load_rom(rom)?;
 
-        Self { cpu }
+        Ok(Self { cpu, run_limit: RUN_LIMIT })
+    }
+
+    /// Maps a DMG or CGB boot ROM over the start of the cartridge and resets the system to its power on state,
+    /// so execution starts in the boot ROM instead of at the cartridge entry point
+    ///
+    /// The boot ROM unmaps itself by writing to FF50 once it's done
+    ///
+    /// ### Return Variants
+    /// - `Err(RomLoadError::InvalidBootRomSize)` if `data` isn't 256 or 2304 bytes long
+    pub fn set_boot_rom(&mut self, data: &[u8]) -> Result<(), RomLoadError> {
+        self.cpu.memory.map_boot_rom(data)?;
+        self.cpu.power_on();
+
+        Ok(())
+    }
+
+    /// Returns whether the cartridge's rumble motor is currently on
+    ///
+    /// Frontends should poll this once per frame and drive the host's force feedback with it
+    pub fn rumble(&self) -> bool {
+        self.cpu.memory.rumble()
+    }
+
+    /// Returns whether the cartridge's infrared LED is currently lit
+    pub fn ir_led(&self) -> bool {
+        self.cpu.memory.ir_led()
+    }
+
+    /// Sets whether the cartridge's infrared receiver is currently seeing light
+    ///
+    /// Two instances can be linked by feeding each one's `Gbc::ir_led()` into the other after every step
+    pub fn set_ir_input(&mut self, light: bool) {
+        self.cpu.memory.set_ir_input(light);
+    }
+
+    /// Sets the tilt seen by the cartridge's accelerometer in g, where positive values are right and down
+    ///
+    /// Only MBC7 cartridges have an accelerometer, for anything else this does nothing
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.cpu.memory.set_tilt(x, y);
+    }
+
+    /// Sets where the cartridge's camera sensor gets its images from
+    ///
+    /// Only the Pocket Camera has a sensor, for anything else this does nothing
+    pub fn set_camera_source(&mut self, source: Box<dyn CameraSource>) {
+        self.cpu.memory.set_camera_source(source);
+    }
+
+    /// Returns whether the cartridge has battery backed memory that should be persisted between sessions
+    pub fn has_battery(&self) -> bool {
+        self.cpu.memory.has_battery()
+    }
+
+    /// Exports battery backed cartridge memory in the raw `.sav` layout, or `None` if the cartridge has no battery
+    pub fn export_save(&self) -> Option<Vec<u8>> {
+        self.cpu.memory.export_save()
+    }
+
+    /// Imports battery backed cartridge memory from the raw `.sav` layout
+    pub fn import_save(&mut self, data: &[u8]) -> Result<(), SaveError> {
+        self.cpu.memory.import_save(data)
     }
 }
 
@@ -35,6 +132,14 @@ impl<T: Memory> Gbc<T> {
         self.cpu.load_rom(data);
     }
 
+    /// Loads cartridge data into ROM
+    ///
+    /// ### Return Variants
+    /// - `Err(RomLoadError::Oversized)` if the data doesn't fit in the cartridge ROM
+    pub fn try_load_rom(&mut self, data: &[u8]) -> Result<(), RomLoadError> {
+        self.cpu.try_load_rom(data)
+    }
+
     /// Run one instruction
     /// 
     /// The second part of the return value is whether the framebuffer is ready to draw
@@ -42,13 +147,208 @@ impl<T: Memory> Gbc<T> {
         (self.cpu.step(), self.cpu.ppu.draw_ready)
     }
 
+    /// Runs one instruction, and if it called a routine or an interrupt was serviced, runs until that returns
+    ///
+    /// Stops early on a breakpoint, STOP, an error or the CPU locking up, and returns like `Gbc::step()`.
+    /// Also gives up after `run_limit` T-cycles, or once the CPU is halted with every interrupt disabled in IE
+    /// so nothing can wake it, check PC for whether the routine returned
+    pub fn step_over(&mut self) -> (Result<CpuStatus, CpuError>, bool) {
+        let start = self.cpu.tick;
+        let sp = self.cpu.regs.sp;
+        let out = self.step();
+
+        self.finish_calls(out, sp, start)
+    }
+
+    /// Runs until the current routine returns to its caller, stepping over any routines it calls
+    ///
+    /// Stops early or gives up like `Gbc::step_over()`
+    pub fn step_out(&mut self) -> (Result<CpuStatus, CpuError>, bool) {
+        let start = self.cpu.tick;
+
+        loop {
+            let sp = self.cpu.regs.sp;
+            let out = self.step();
+
+            // SP as the instruction left it, before any interrupt pushed PC
+            let instruction_sp = if self.cpu.serviced.is_some() {
+                self.cpu.regs.sp.wrapping_add(2)
+            } else {
+                self.cpu.regs.sp
+            };
+
+            // conditional returns that aren't taken leave SP alone
+            let returned = matches!(out.0, Ok(CpuStatus::Run(Instruction::RET(_) | Instruction::RETI)))
+                && instruction_sp != sp;
+
+            let out = self.finish_calls(out, sp, start);
+            if returned || Self::should_stop(&out.0) || self.gave_up(start) {
+                return out;
+            }
+        }
+    }
+
+    /// Sets the trace written before every instruction, or turns tracing off with `None`
+    ///
+    /// Returns the previous trace, so its output can be flushed or inspected
+    pub fn set_trace(&mut self, trace: Option<Trace>) -> Option<Trace> {
+        std::mem::replace(&mut self.cpu.trace, trace)
+    }
+
+    /// Returns the routines that were called and haven't returned yet, innermost first
+    ///
+    /// Frames whose return address was skipped over by moving SP by hand are dropped, check
+    /// `Cpu::call_stack` for whether that happened
+    pub fn backtrace(&self) -> impl Iterator<Item = &Frame> {
+        self.cpu.call_stack.frames.iter().rev()
+    }
+
+    /// Disassembles the instructions in `range` as they're currently mapped
+    ///
+    /// Use `disassemble_range()` on `cpu.memory` to read 4000-7FFF from a bank that isn't mapped
+    pub fn disassemble(&self, range: RangeInclusive<u16>) -> Vec<Disassembly> {
+        disassemble_range(&*self.cpu.memory, range, None)
+    }
+
+    /// Runs until PC is `addr`, always running at least one instruction
+    ///
+    /// Stops early or gives up like `Gbc::step_over()`
+    pub fn run_to(&mut self, addr: u16) -> (Result<CpuStatus, CpuError>, bool) {
+        let start = self.cpu.tick;
+
+        loop {
+            let out = self.step();
+
+            if self.cpu.regs.pc == addr || Self::should_stop(&out.0) || self.gave_up(start) {
+                return out;
+            }
+        }
+    }
+
+    /// Runs until `n` more frames are ready to draw, counting a frame's worth of cycles as a frame while the LCD is off
+    ///
+    /// Stops early like `Gbc::step_over()`, the framebuffer is only ready when all `n` frames ran
+    ///
+    /// Nothing runs when `n` is 0, the status is then the one the CPU is already in, with `Run` holding the
+    /// instruction at PC
+    pub fn run_frames(&mut self, n: usize) -> (Result<CpuStatus, CpuError>, bool) {
+        if n == 0 {
+            return (self.status(), self.cpu.ppu.draw_ready);
+        }
+
+        let mut frames = 0;
+        let mut start = self.cpu.tick;
+        self.set_drawn();
+
+        loop {
+            let out = self.step();
+            if Self::should_stop(&out.0) {
+                return out;
+            }
+
+            let lcd_off = !self.cpu.ppu.lcdc.lcd_enable && self.cpu.tick - start >= FRAME_CYCLES;
+            if out.1 || lcd_off {
+                frames += 1;
+                start = self.cpu.tick;
+
+                if frames >= n {
+                    return (out.0, true);
+                }
+                self.set_drawn();
+            }
+        }
+    }
+
+    /// If the step that returned `out` called a routine or serviced an interrupt, runs until that returns
+    ///
+    /// `sp` is SP from before the step, and `start` is the cycle count the run started at
+    fn finish_calls(
+        &mut self,
+        out: (Result<CpuStatus, CpuError>, bool),
+        sp: u16,
+        start: usize,
+    ) -> (Result<CpuStatus, CpuError>, bool) {
+        if Self::should_stop(&out.0) {
+            return out;
+        }
+
+        let called = matches!(out.0, Ok(CpuStatus::Run(Instruction::CALL(_) | Instruction::RST(_))));
+
+        // an untaken CALL only pushes when an interrupt is serviced after it, which returns to the same place
+        if called && self.cpu.regs.sp != sp {
+            let ret = self.peek_word(sp.wrapping_sub(2));
+            self.run_until_return(ret, sp, start)
+        } else if self.cpu.serviced.is_some() {
+            let sp = self.cpu.regs.sp;
+            self.run_until_return(self.peek_word(sp), sp.wrapping_add(2), start)
+        } else {
+            out
+        }
+    }
+
+    /// Steps until PC is `pc` with SP at `sp`, which is where a routine called from `pc` returns to
+    fn run_until_return(&mut self, pc: u16, sp: u16, start: usize) -> (Result<CpuStatus, CpuError>, bool) {
+        loop {
+            let out = self.step();
+
+            let returned = self.cpu.regs.pc == pc && self.cpu.regs.sp == sp;
+            if returned || Self::should_stop(&out.0) || self.gave_up(start) {
+                return out;
+            }
+        }
+    }
+
+    /// Returns the status the CPU is in without running anything, with `Run` holding the instruction at PC
+    fn status(&self) -> Result<CpuStatus, CpuError> {
+        if self.cpu.locked {
+            return Ok(CpuStatus::Locked);
+        } else if self.cpu.stop {
+            return Ok(CpuStatus::Stop);
+        } else if self.cpu.halted {
+            return Ok(CpuStatus::Halt);
+        }
+
+        let pc = self.cpu.regs.pc;
+        let next = disassemble(&*self.cpu.memory, pc, None);
+
+        match next.instruction {
+            Some(instruction) => Ok(CpuStatus::Run(instruction)),
+            None => Err(CpuError::IllegalOpcode { pc, opcode: next.bytes[0] }),
+        }
+    }
+
+    /// Returns whether running several instructions has to stop and hand control back to the frontend
+    fn should_stop(status: &Result<CpuStatus, CpuError>) -> bool {
+        !matches!(status, Ok(CpuStatus::Run(_) | CpuStatus::Halt | CpuStatus::BlockedByDma))
+    }
+
+    /// Returns whether a run that started at cycle `start` has used up `run_limit`, or can't get anywhere
+    /// because the CPU is halted with no interrupt enabled to wake it
+    fn gave_up(&self, start: usize) -> bool {
+        let ie = self.cpu.memory.load(memory::IE).unwrap_or(0);
+
+        self.cpu.tick.saturating_sub(start) >= self.run_limit || (self.cpu.halted && ie & 0x1F == 0)
+    }
+
+    fn peek_word(&self, addr: u16) -> u16 {
+        u16::from_le_bytes([self.cpu.peek(addr), self.cpu.peek(addr.wrapping_add(1))])
+    }
+
     pub fn set_drawn(&mut self) {
         self.cpu.ppu.draw_ready = false;
     }
 
     /// Reads the serial buffer
+    ///
+    /// Reading a byte completes its transfer, which requests the serial interrupt
     pub fn read_serial(&mut self) -> Option<u8> {
+        let transferring = self.cpu.memory.load(memory::SC).is_some_and(|sc| sc & (1 << 7) > 0);
         let byte = self.cpu.memory.read_serial();
+
+        if transferring {
+            self.cpu.request_interrupt(Interrupt::Serial);
+        }
+
         if byte == 0xFF {
             None
         } else {
@@ -71,10 +371,187 @@ impl<T: Memory> Gbc<T> {
     }
 
     fn set_button(&mut self, button: Button, to: bool) {
-        *self.cpu.host_input.get_mut(button) = to
+        let before = self.cpu.joyp.serialize(self.cpu.host_input);
+        *self.cpu.host_input.get_mut(button) = to;
+        let after = self.cpu.joyp.serialize(self.cpu.host_input);
+
+        // a selected line going low requests the joypad interrupt
+        if before & !after & 0x0F > 0 {
+            self.cpu.request_interrupt(Interrupt::Joypad);
+        }
     }
 
     pub fn disable_ppu(&mut self) {
         self.cpu.ppu.enabled = false;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cpu::{CpuEvent, CpuStatus, Instruction, Interrupt, StackMismatch},
+        input::ButtonSelection,
+        memory::{self, mbc::{MbcSelector, RamSize}, Memory, Mmu},
+        Button, Model,
+    };
+
+    use super::{Gbc, FRAME_CYCLES};
+
+    /// A system running a routine at 0110 that calls another at 0120, then looping at 0104
+    fn init() -> Gbc<Mmu> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100..0x0106].copy_from_slice(&[0xCD, 0x10, 0x01, 0x00, 0x18, 0xFE]);
+        rom[0x0110..0x0114].copy_from_slice(&[0xCD, 0x20, 0x01, 0xC9]);
+        rom[0x0120..0x0122].copy_from_slice(&[0x00, 0xC9]);
+
+        let mut gbc = Gbc::new(MbcSelector::NoMbc(RamSize::Zero, false), Model::Dmg, false, true);
+        gbc.load_rom(&rom);
+        gbc
+    }
+
+    #[test]
+    fn boot_rom() {
+        let mut gbc = init();
+        assert!(gbc.run_frames(1).0.is_ok());
+
+        // NOPs, then unmaps itself with `ld a, 1 / ldh [$50], a` just before the cartridge entry point
+        let mut boot = vec![0; 0x100];
+        boot[0xFC..].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+        gbc.set_boot_rom(&boot).unwrap();
+        assert_eq!((gbc.cpu.regs.pc, gbc.cpu.tick), (0x0000, 0));
+
+        while gbc.cpu.regs.pc != 0x0100 {
+            assert!(gbc.step().0.is_ok());
+        }
+
+        assert!(!gbc.cpu.memory.boot_rom_mapped());
+        assert_eq!(gbc.cpu.tick, 0xFC * 4 + 8 + 12);
+        assert_eq!(gbc.cpu.memory.load(memory::LY), Some(0));
+        assert_eq!(gbc.cpu.ppu.coords.y, 0);
+        assert!(!gbc.cpu.double_speed && !gbc.cpu.halted);
+    }
+
+    #[test]
+    fn input_interrupts() {
+        let mut gbc = init();
+        gbc.cpu.breakpoint_controls.set(CpuEvent::InterruptRequest(Interrupt::Joypad));
+        gbc.cpu.breakpoint_controls.set(CpuEvent::InterruptRequest(Interrupt::Serial));
+        gbc.cpu.memory.set(memory::IF, 0x00);
+
+        // only buttons on a selected line request the joypad interrupt
+        gbc.cpu.joyp.change_selection(ButtonSelection::DPAD).unwrap();
+        gbc.press_button(Button::A);
+        assert_eq!(gbc.cpu.memory.load(memory::IF), Some(0x00));
+        gbc.press_button(Button::Up);
+        assert_eq!(gbc.cpu.memory.load(memory::IF), Some(0x10));
+        assert!(matches!(gbc.step().0, Ok(CpuStatus::Break(_, CpuEvent::InterruptRequest(Interrupt::Joypad)))));
+
+        // reading a byte completes its transfer
+        gbc.cpu.memory.set(memory::SB, 0x42);
+        gbc.cpu.memory.set(memory::SC, 0x81);
+        assert_eq!(gbc.read_serial(), Some(0x42));
+        assert_eq!(gbc.cpu.memory.load(memory::IF), Some(0x18));
+        assert!(matches!(gbc.step().0, Ok(CpuStatus::Break(_, CpuEvent::InterruptRequest(Interrupt::Serial)))));
+    }
+
+    #[test]
+    fn step_over() {
+        let mut gbc = init();
+        let sp = gbc.cpu.regs.sp;
+
+        assert!(matches!(gbc.step_over().0, Ok(CpuStatus::Run(_))));
+        assert_eq!(gbc.cpu.regs.pc, 0x0103);
+        assert_eq!(gbc.cpu.regs.sp, sp);
+
+        // breakpoints inside the routine still stop it
+        let mut gbc = init();
+        gbc.cpu.breakpoint_controls.breakpoints.push(CpuEvent::Pc(0x0121));
+        assert!(matches!(gbc.step_over().0, Ok(CpuStatus::Break(..))));
+        assert_eq!(gbc.cpu.regs.pc, 0x0121);
+    }
+
+    #[test]
+    fn step_out() {
+        let mut gbc = init();
+
+        assert!(matches!(gbc.run_to(0x0121).0, Ok(CpuStatus::Run(_))));
+        assert_eq!(gbc.cpu.regs.pc, 0x0121);
+
+        assert!(matches!(gbc.step_out().0, Ok(CpuStatus::Run(_))));
+        assert_eq!(gbc.cpu.regs.pc, 0x0113);
+        assert!(matches!(gbc.step_out().0, Ok(CpuStatus::Run(_))));
+        assert_eq!(gbc.cpu.regs.pc, 0x0103);
+    }
+
+    #[test]
+    fn run_frames() {
+        let mut gbc = init();
+
+        let (status, ready) = gbc.run_frames(2);
+        assert!(matches!(status, Ok(CpuStatus::Run(_))));
+        assert!(ready);
+        assert_eq!(gbc.cpu.ppu.coords.y, 144);
+
+        // no frames runs nothing
+        gbc.set_drawn();
+        let tick = gbc.cpu.tick;
+        let (status, ready) = gbc.run_frames(0);
+        assert!(matches!(status, Ok(CpuStatus::Run(Instruction::JR(_)))));
+        assert!(!ready);
+        assert_eq!(gbc.cpu.tick, tick);
+    }
+
+    #[test]
+    fn give_up() {
+        // nothing can wake the CPU from `di / halt` with IE clear
+        let mut gbc = Gbc::new_flat(Model::Dmg, false, true);
+        gbc.load_program(&crate::gbasm!(di / halt / nop));
+        gbc.cpu.memory.set(memory::IE, 0x00);
+
+        assert!(gbc.run_to(0x0200).0.is_ok());
+        assert!(gbc.cpu.halted);
+        assert_eq!(gbc.cpu.regs.pc, 0x0102);
+        assert!(gbc.cpu.tick < 100);
+
+        // a routine that never returns runs until the limit
+        let mut gbc = Gbc::new_flat(Model::Dmg, false, true);
+        gbc.load_program(&crate::gbasm!(call routine / nop / routine: jr routine));
+        gbc.run_limit = FRAME_CYCLES;
+
+        assert!(matches!(gbc.step_over().0, Ok(CpuStatus::Run(_))));
+        assert_eq!(gbc.cpu.regs.pc, 0x0104);
+        assert!((FRAME_CYCLES..FRAME_CYCLES + 24).contains(&gbc.cpu.tick));
+
+        assert!(matches!(gbc.step_out().0, Ok(CpuStatus::Run(_))));
+        assert!(matches!(gbc.run_to(0x0200).0, Ok(CpuStatus::Run(_))));
+        assert_eq!(gbc.cpu.regs.pc, 0x0104);
+    }
+
+    #[test]
+    fn assembled_program() {
+        let mut gbc = Gbc::new_flat(Model::Dmg, false, true);
+        gbc.load_program(&crate::gbasm!(ld a, 5 / ld b, 3 / add b / done: jr done));
+
+        assert!(matches!(gbc.run_to(0x0105).0, Ok(CpuStatus::Run(_))));
+        assert_eq!(gbc.cpu.regs.a, 8);
+    }
+
+    #[test]
+    fn backtrace() {
+        let mut gbc = init();
+
+        assert!(matches!(gbc.run_to(0x0121).0, Ok(CpuStatus::Run(_))));
+        let sites: Vec<(u16, u16)> = gbc.backtrace().map(|f| (f.call_site, f.target)).collect();
+        assert_eq!(sites, vec![(0x0110, 0x0120), (0x0100, 0x0110)]);
+
+        assert!(matches!(gbc.step_out().0, Ok(CpuStatus::Run(_))));
+        assert_eq!(gbc.backtrace().count(), 1);
+
+        // resetting SP drops the frame without corrupting the stack
+        gbc.cpu.regs.sp = 0xFFFE;
+        gbc.cpu.regs.pc = 0x0100;
+        assert!(matches!(gbc.step().0, Ok(CpuStatus::Run(_))));
+        assert_eq!(gbc.backtrace().count(), 1);
+        assert_eq!(gbc.cpu.call_stack.last_mismatch, Some((0x0100, StackMismatch::Discarded(1))));
+    }
+}
@@ -3,21 +3,32 @@ mod gameboy;
 pub mod memory;
 mod ppu;
 mod input;
+mod model;
 
 pub use gameboy::{Gbc, MBC_ADDR};
-pub use memory::{mbc::MbcSelector, mbc::RamSize, mbc::RomSize, Mmu};
-pub use cpu::{CpuStatus, CpuError, Flags, Instruction, CpuEvent, CpuReg, CpuFlag, Registers, IoRegs};
+pub use memory::{
+    mbc::header::{CartridgeType, CgbFlag, Destination, Mapper},
+    mbc::{
+        CameraSource, CartridgeHeader, Mbc3Features, Mbc5Features, MbcSelector, RamSize, RomLoadError, RomSize,
+        SaveError,
+        CAMERA_HEIGHT, CAMERA_WIDTH,
+    },
+    Mmu,
+};
+pub use cpu::{
+    BinOp, Comparison, ConditionalBreakpoint, CpuStatus, CpuError, Flags, Instruction, CpuEvent, CpuReg, CpuFlag,
+    Expr, ExprContext, ExprError, Interrupt, Registers, IoRegs, Access, WatchHit, Watchpoint, CallStack, Frame,
+    StackMismatch, disassemble, disassemble_range, Disassembly, assemble, AsmError, Trace, TraceFormat,
+};
 pub use ppu::PpuStatus;
 pub use input::{Button, Joyp};
+pub use model::Model;
 
 pub fn get_mbc(rom: &[u8]) -> MbcSelector {
-    let rom_size = RomSize::from_byte(rom[0x0148]);
-    let ram_size = RamSize::from_byte(rom[0x0149]);
-    
-    match rom[MBC_ADDR] {
-        0x00 => MbcSelector::NoMbc,
-        0x01 => MbcSelector::Mbc1(rom_size, RamSize::Zero),
-        0x03 => MbcSelector::Mbc1(rom_size, ram_size),
-        e => panic!("Unsupported MBC ({e:#04X})"),
-    }
-}
\ No newline at end of file
+    CartridgeHeader::parse(rom).mbc_selector()
+}
+
+/// Picks the MBC described by `rom`'s header, without panicking on a bad header
+pub fn try_get_mbc(rom: &[u8]) -> Result<MbcSelector, RomLoadError> {
+    CartridgeHeader::try_parse(rom)?.try_mbc_selector()
+}
@@ -1,8 +1,9 @@
 use self::{
     bank::{VramBank, WramBank},
-    init::init_io,
-    mbc::{init_mbc, Mbc, MbcSelector},
+    init::{init_io, init_io_power_on},
+    mbc::{try_init_mbc, CameraSource, Mbc, MbcSelector, RomLoadError, SaveError},
 };
+use crate::Model;
 
 mod bank;
 mod init;
@@ -14,6 +15,8 @@ pub const OAM: u16 = 0xFE00;
 pub const OAM_END: u16 = 0xFE9F;
 /// Joypad input
 pub const JOYP: u16 = 0xFF00;
+/// Serial transfer data
+pub const SB: u16 = 0xFF01;
 /// Serial transfer control
 pub const SC: u16 = 0xFF02;
 /// Internal timer
@@ -51,6 +54,12 @@ pub const OBP2: u16 = 0xFF49;
 pub const WY: u16 = 0xFF4A;
 /// Window X position + 7
 pub const WX: u16 = 0xFF4B;
+/// CGB speed switch, bit 7 is the current speed and bit 0 arms a switch on the next STOP
+pub const KEY1: u16 = 0xFF4D;
+/// VRAM bank select
+pub const VBK: u16 = 0xFF4F;
+/// Boot ROM disable, writing a value with bit 0 set unmaps the boot ROM until the next power cycle
+pub const BOOT: u16 = 0xFF50;
 /// WRAM bank select
 pub const SVBK: u16 = 0xFF70;
 /// High RAM
@@ -75,7 +84,20 @@ pub trait Memory {
     /// - `None` if the selected cell is uninitialized
     fn load(&self, addr: u16) -> Option<u8>;
     
-    fn load_rom(&mut self, data: &[u8]);
+    /// Loads cartridge data into ROM
+    ///
+    /// ### Panic Conditions
+    /// - Panics if the data doesn't fit in ROM, see `Memory::try_load_rom()`
+    fn load_rom(&mut self, data: &[u8]) {
+        self.try_load_rom(data).unwrap_or_else(|e| panic!("{e}"));
+    }
+
+    /// Loads cartridge data into ROM
+    ///
+    /// ### Return Variants
+    /// - `Ok(())` if the data fit in ROM
+    /// - `Err(RomLoadError::Oversized)` if it didn't
+    fn try_load_rom(&mut self, data: &[u8]) -> Result<(), RomLoadError>;
     
     /// Sets the cell at address `addr` to the value stored in `value`
     fn set(&mut self, addr: u16, value: u8);
@@ -96,6 +118,22 @@ pub trait Memory {
     ///
     /// Mutable so it can reset SC.7 to signal that the byte was sent
     fn read_serial(&mut self) -> u8;
+
+    /// Returns the ROM bank currently mapped into 4000-7FFF, always `1` without an MBC
+    fn rom_bank(&self) -> usize {
+        1
+    }
+
+    /// Gets the byte at `addr` in ROM bank `bank`, whether or not the bank is mapped
+    ///
+    /// Only the low 14 bits of `addr` are used, without an MBC only banks `0` and `1` exist
+    fn load_rom_bank(&self, bank: usize, addr: u16) -> Option<u8> {
+        if bank > 1 {
+            return None;
+        }
+
+        self.load((bank as u16 * 0x4000) | (addr & 0x3FFF))
+    }
 }
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
@@ -110,6 +148,11 @@ pub(crate) enum MmuAddr {
     Ie,
 }
 
+/// Size of the DMG boot ROM
+const DMG_BOOT_LEN: usize = 0x100;
+/// Size of the CGB boot ROM, including the unused 0100-01FF gap
+const CGB_BOOT_LEN: usize = 0x900;
+
 /// Memory management unit
 ///
 /// The main interfaces of this structure are `Mmu::get()` and `Mmu::set()`
@@ -125,29 +168,122 @@ pub struct Mmu {
     // E000 - FDFF is mapped to $C000 - $DDFF
     // FE00 - FE9F
     oam: [Option<u8>; 0xA0], // sprite attribute table, display information for objects are stored here
-    // FEA0 - FEFF is unusable
-    prohibited: [Option<u8>; 0x60],
+    // FEA0 - FEFF is unusable, see `Mmu::load()` for what it reads back as
     // FF00 - FF7F
     pub io: [Option<u8>; 0x80], // io registers for interfacing with peripherals
     // FF80 - FFFE
     hram: [Option<u8>; 0x7F], // high ram, physically located within the cpu, can be used during DMA transfers
     // FFFF
     ie: u8, // interrupt enable register
+    // 0000 - 00FF
+    // 0200 - 08FF on CGB
+    boot_rom: Option<Box<[u8]>>, // overlaid on top of the cartridge until FF50 is written to
+    model: Model, // gates the CGB banking registers and how the prohibited segment behaves
 }
 
 impl Mmu {
-    pub fn new(mbc_kind: MbcSelector) -> Self {
-        Self {
-            mbc: init_mbc(mbc_kind),
+    /// ### Panic Conditions
+    /// - Panics if the ROM or RAM size can't be used with the MBC, see `Mmu::try_new()`
+    pub fn new(mbc_kind: MbcSelector, model: Model) -> Self {
+        Self::try_new(mbc_kind, model).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// ### Return Variants
+    /// - `Err(RomLoadError::HeaderMismatch)` if the ROM or RAM size can't be used with the MBC
+    pub fn try_new(mbc_kind: MbcSelector, model: Model) -> Result<Self, RomLoadError> {
+        Ok(Self {
+            mbc: try_init_mbc(mbc_kind)?,
             vram: Box::new(VramBank::new()),
             wram: Box::new(WramBank::new()),
             oam: [None; 0xA0],
-            prohibited: [None; 0x60],
-            io: init_io(),
+            io: init_io(model),
             hram: [None; 0x7F],
             ie: 0,
+            boot_rom: None,
+            model,
+        })
+    }
+
+    /// Maps a boot ROM over the start of the cartridge and resets IO to its power on state
+    ///
+    /// DMG boot ROMs cover 0000-00FF, CGB boot ROMs also cover 0200-08FF with the cartridge header showing through
+    /// the gap between them. Either is unmapped when the boot ROM writes to FF50
+    ///
+    /// ### Return Variants
+    /// - `Err(RomLoadError::InvalidBootRomSize)` if `data` isn't 256 or 2304 bytes long
+    pub fn map_boot_rom(&mut self, data: &[u8]) -> Result<(), RomLoadError> {
+        if data.len() != DMG_BOOT_LEN && data.len() != CGB_BOOT_LEN {
+            return Err(RomLoadError::InvalidBootRomSize(data.len()));
         }
+
+        self.boot_rom = Some(data.into());
+        self.io = init_io_power_on(self.model);
+
+        Ok(())
+    }
+
+    /// Returns the hardware model this memory is behaving as
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Returns whether the boot ROM is still mapped over the cartridge
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom.is_some()
+    }
+
+    /// Returns whether the cartridge's rumble motor is currently on
+    pub fn rumble(&self) -> bool {
+        self.mbc.rumble()
     }
+
+    /// Returns whether the cartridge's infrared LED is currently lit
+    pub fn ir_led(&self) -> bool {
+        self.mbc.ir_led()
+    }
+
+    /// Sets whether the cartridge's infrared receiver is currently seeing light
+    pub fn set_ir_input(&mut self, light: bool) {
+        self.mbc.set_ir_input(light);
+    }
+
+    /// Sets the tilt seen by the cartridge's accelerometer in g
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mbc.set_tilt(x, y);
+    }
+
+    /// Sets where the cartridge's camera sensor gets its images from
+    pub fn set_camera_source(&mut self, source: Box<dyn CameraSource>) {
+        self.mbc.set_camera_source(source);
+    }
+
+    /// Returns whether the cartridge has battery backed memory that should be persisted
+    pub fn has_battery(&self) -> bool {
+        self.mbc.has_battery()
+    }
+
+    /// Exports battery backed cartridge memory in the raw `.sav` layout
+    ///
+    /// ### Return Variants
+    /// - `Some(data)` if the cartridge has a battery
+    /// - `None` if there is nothing to persist
+    pub fn export_save(&self) -> Option<Vec<u8>> {
+        if self.mbc.has_battery() {
+            Some(self.mbc.dump_ram())
+        } else {
+            None
+        }
+    }
+
+    /// Imports battery backed cartridge memory from the raw `.sav` layout
+    pub fn import_save(&mut self, data: &[u8]) -> Result<(), SaveError> {
+        if !self.mbc.has_battery() {
+            return Err(SaveError::NoBattery);
+        }
+
+        self.mbc.restore_ram(data)
+    }
+
     /// Translates a global memory address to an internally usable enum variant
     fn translate(addr: u16) -> MmuAddr {
         if addr < 0x8000 {
@@ -216,16 +352,28 @@ impl Memory for Mmu {
     /// - `Some<u8>` if the selected cell is initialized
     /// - `None` if the selected cell is uninitialized
     fn load(&self, addr: u16) -> Option<u8> {
+        if let Some(boot) = self.boot_rom.as_deref() {
+            match addr {
+                0x0000..=0x00FF => return Some(boot[addr as usize]),
+                0x0200..=0x08FF if boot.len() == CGB_BOOT_LEN => return Some(boot[addr as usize]),
+                _ => {}
+            }
+        }
+
         match Self::translate(addr) {
             MmuAddr::Mbc(a) => self.mbc.load(a),
             MmuAddr::Vram(a) => self.vram.load(a),
             MmuAddr::Wram(a) => self.wram.load(a),
             MmuAddr::Oam(a) => self.oam[a as usize],
-            // On CGB revision E, reading from this segment returns the high nibble of the lower address byte twice
-            MmuAddr::Prohibited(a) => {
-                // let nibble = (addr & 0x00F0) as u8;
-                // Some(nibble | nibble >> 4)
-                self.prohibited[a as usize]
+            // On CGB revision E and AGB, reading from this segment returns the high nibble of the lower address byte
+            // twice, earlier models return 0
+            MmuAddr::Prohibited(_) => {
+                if self.model.is_cgb() {
+                    let nibble = (addr & 0x00F0) as u8;
+                    Some(nibble | nibble >> 4)
+                } else {
+                    Some(0x00)
+                }
             }
             MmuAddr::Io(a) => {
                 self.io[a as usize]
@@ -235,27 +383,51 @@ impl Memory for Mmu {
         }
     }
 
-    fn load_rom(&mut self, data: &[u8]) {
-        self.mbc.load_rom(data);
+    fn try_load_rom(&mut self, data: &[u8]) -> Result<(), RomLoadError> {
+        self.mbc.try_load_rom(data)
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.mbc.rom_bank()
+    }
+
+    fn load_rom_bank(&self, bank: usize, addr: u16) -> Option<u8> {
+        self.mbc.load_rom_bank(bank, addr)
     }
 
     /// Sets the cell at address `addr` to the value stored in `value`
     ///
     /// ### Side Effects
     /// This method may have internal side effects, as listed below:
-    /// - If `addr` == `0xFF70`, the selected WRAM bank will be changed using the new value
+    /// - If `addr` == `0xFF70` on CGB, the selected WRAM bank will be changed using the new value
+    /// - If `addr` == `0xFF4F` on CGB, the selected VRAM bank will be changed using the new value
     fn set(&mut self, addr: u16, value: u8) {
         match Self::translate(addr) {
             MmuAddr::Mbc(a) => self.mbc.set(a, value),
             MmuAddr::Vram(a) => self.vram.set(a, value),
             MmuAddr::Wram(a) => self.wram.set(a, value),
             MmuAddr::Oam(a) => self.oam[a as usize] = Some(value),
-            MmuAddr::Prohibited(a) => self.prohibited[a as usize] = Some(value),
+            MmuAddr::Prohibited(_) => {}
             MmuAddr::Io(a) => {
-                // if addr == SVBK {
-                //     // WRAM Bank Select
-                //     self.wram.select(value);
-                // }
+                match addr {
+                    SVBK | VBK if !self.model.is_cgb() => return,
+                    SVBK => {
+                        // WRAM Bank Select, bank 0 selects bank 1
+                        self.wram.select((value & 0x07).max(1));
+                        self.io[a as usize] = Some(0xF8 | value);
+                        return;
+                    }
+                    VBK => {
+                        self.vram.select(value & 0x01);
+                        self.io[a as usize] = Some(0xFE | value);
+                        return;
+                    }
+                    _ => {}
+                }
+
+                if addr == BOOT && value & 1 > 0 {
+                    self.boot_rom = None;
+                }
                 
                 self.io[a as usize] = Some(value);
             }
@@ -326,8 +498,13 @@ impl Memory for FlatMemory {
         self.inner[start as usize..=end as usize].to_vec()
     }
 
-    fn load_rom(&mut self, data: &[u8]) {
+    fn try_load_rom(&mut self, data: &[u8]) -> Result<(), RomLoadError> {
+        if data.len() > self.inner.len() {
+            return Err(RomLoadError::Oversized { max: self.inner.len(), found: data.len() });
+        }
+
         self.inner[0..data.len()].copy_from_slice(data);
+        Ok(())
     }
 
     fn set(&mut self, addr: u16, value: u8) {
@@ -345,12 +522,12 @@ impl Memory for FlatMemory {
 
 #[cfg(test)]
 mod tests {
-    use crate::memory::Memory;
+    use crate::{memory::Memory, Model};
 
-    use super::{mbc::MbcSelector, Mmu, MmuAddr};
+    use super::{mbc::{MbcSelector, RamSize}, Mmu, MmuAddr};
 
     fn init_nombc() -> Mmu {
-        Mmu::new(MbcSelector::NoMbc)
+        Mmu::new(MbcSelector::NoMbc(RamSize::Two, false), Model::Cgb)
     }
 
     #[test]
@@ -446,4 +623,37 @@ mod tests {
 
         assert_eq!(memory.load(0xFEC8), Some(0xCC));
     }
+
+    #[test]
+    fn dmg_model() {
+        let mut memory = Mmu::new(MbcSelector::NoMbc(RamSize::Two, false), Model::Dmg);
+
+        assert_eq!(memory.load(0xFEC8), Some(0x00));
+        assert_eq!(memory.load(0xFF70), Some(0xFF));
+
+        // there's only one WRAM bank to switch to
+        memory.set(0xD800, 0x10);
+        memory.set(0xFF70, 2);
+        assert_eq!(memory.load(0xD800), Some(0x10));
+        assert_eq!(memory.load(0xFF70), Some(0xFF));
+    }
+
+    #[test]
+    fn boot_rom() {
+        let mut memory = init_nombc();
+        memory.load_rom(&[0x45; 0x8000]);
+
+        let mut boot = vec![0x31; 0x900];
+        boot[0x0100] = 0x00;
+        memory.map_boot_rom(&boot).unwrap();
+
+        assert_eq!(memory.load(0x0000), Some(0x31));
+        // the header shows through the gap in CGB boot ROMs
+        assert_eq!(memory.load(0x0100), Some(0x45));
+        assert_eq!(memory.load(0x0800), Some(0x31));
+
+        memory.set(0xFF50, 0x11);
+        assert_eq!(memory.load(0x0000), Some(0x45));
+        assert_eq!(memory.load(0x0800), Some(0x45));
+    }
 }
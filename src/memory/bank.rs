@@ -8,7 +8,7 @@ pub struct VramBank {
 pub struct WramBank {
     main: [Option<u8>; 0x1000],
     memory: [[Option<u8>; 0x1000]; 7],
-    selected: u8,
+    selected: u8, // index into `memory`, which holds banks 1 - 7
 }
 
 impl VramBank {
@@ -66,7 +66,7 @@ impl WramBank {
         Self {
             main: [None; 0x1000],
             memory: [[None; 0x1000]; 7],
-            selected: 1,
+            selected: 0,
         }
     }
 
@@ -107,12 +107,12 @@ impl WramBank {
     /// Selects the bank to be used when performing `Self::get()` and `Self::set()` operations
     ///
     /// ### Panic Conditions
-    /// This method will panic if `bank` is outside of the bounds `0 - 7`
+    /// This method will panic if `bank` is outside of the bounds `1 - 7`
     pub fn select(&mut self, bank: u8) {
-        if bank > 7 {
+        if !(1..=7).contains(&bank) {
             panic!("Invalid WRAM bank selected: {bank}");
         }
 
-        self.selected = bank;
+        self.selected = bank - 1;
     }
 }
@@ -1,9 +1,11 @@
-/// Returns a new initialized IO memory segment
+use crate::Model;
+
+/// Returns a new initialized IO memory segment, as `model`'s boot ROM leaves it
 ///
 /// Some cells are not meant to be initialized at boot, and are set to None
-pub fn init_io() -> [Option<u8>; 0x80] {
-    // Initial values from mooneye's test roms (misc/boot_hwio-C.s)
-    let initial: [u8; 0x80] = [
+pub fn init_io(model: Model) -> [Option<u8>; 0x80] {
+    // Initial values from the DMG column of pandocs' power up sequence, with the CGB-only registers unmapped
+    let mut initial: [u8; 0x80] = [
         0xFF, 0x00, 0x7E, 0xFF, 0x00, 0x00, 0x00, 0xF8, // FF00
         0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE1, // FF08
         0x80, 0xBF, 0xF3, 0xFF, 0xBF, 0xFF, 0x3F, 0x00, // FF10
@@ -12,16 +14,43 @@ pub fn init_io() -> [Option<u8>; 0x80] {
         0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, // FF28
         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // FF30
         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // FF38
-        0x00, 0x00, 0x00, 0x00, 0x91, 0x00, 0x00, 0xFC, // FF40
-        0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFE, // FF48
+        0x91, 0x85, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFC, // FF40
+        0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, // FF48
         0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // FF50
         0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // FF58
         0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // FF60
-        0xC8, 0xFF, 0xD0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // FF68
-        0xFF, 0xFF, 0x00, 0x00, 0xFF, 0x8F, 0x00, 0x00, // FF70
+        0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // FF68
+        0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // FF70
         0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // FF78
     ];
 
+    initial[0x04] = (model.post_boot_div() >> 8) as u8; // DIV
+
+    match model {
+        Model::Dmg0 => {
+            initial[0x41] = 0x81; // STAT
+        }
+        // the SGB boot ROM leaves the APU in a different state
+        Model::Sgb | Model::Sgb2 => {
+            initial[0x26] = 0xF0; // NR52
+        }
+        // values from mooneye's test roms (misc/boot_hwio-C.s)
+        Model::Cgb | Model::Agb => {
+            initial[0x02] = 0x7F; // SC
+            initial[0x46] = 0x00; // DMA
+            initial[0x4F] = 0xFE; // VBK
+            initial[0x68] = 0xC8; // BCPS
+            initial[0x6A] = 0xD0; // OCPS
+            initial[0x70] = 0xF8; // SVBK
+            initial[0x72] = 0x00;
+            initial[0x73] = 0x00;
+            initial[0x75] = 0x8F;
+            initial[0x76] = 0x00; // PCM12
+            initial[0x77] = 0x00; // PCM34
+        }
+        Model::Dmg | Model::Mgb => {}
+    }
+
     // 1s are initialized, 0s are uninitialized
     let init_mask: [u32; 4] = [
         0b11110111_11111111_11111111_11111111, // FF00 - FF1F
@@ -54,3 +83,32 @@ pub fn init_io() -> [Option<u8>; 0x80] {
 
     memory
 }
+
+/// Returns IO memory as it is at power on, before a boot ROM has run
+///
+/// Differs from `init_io()` in the registers the boot ROM sets up: the APU, LCD and palette are off,
+/// and FF50 still has the boot ROM mapped
+pub fn init_io_power_on(model: Model) -> [Option<u8>; 0x80] {
+    let mut memory = init_io(model);
+
+    // with the APU off the registers are cleared, and read back as their unused bits
+    let apu: [u8; 0x17] = [
+        0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00, // FF10
+        0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF, // FF18
+        0xFF, 0x00, 0x00, 0xBF, 0x00, 0x00, 0x70, // FF20
+    ];
+
+    for (cell, value) in memory[0x10..0x27].iter_mut().zip(apu) {
+        *cell = Some(value);
+    }
+
+    memory[0x04] = Some(0x00); // DIV
+    memory[0x0F] = Some(0xE0); // IF
+    memory[0x40] = Some(0x00); // LCDC
+    memory[0x41] = Some(0x80); // STAT
+    memory[0x44] = Some(0x00); // LY
+    memory[0x47] = Some(0x00); // BGP
+    memory[0x50] = Some(0xFE); // BOOT
+
+    memory
+}
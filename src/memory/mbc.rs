@@ -1,19 +1,137 @@
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+mod camera;
+mod five;
+pub mod header;
+mod huc1;
+mod huc3;
+mod mmm01;
 mod none;
 mod one;
+mod seven;
+mod three;
+mod two;
 
+pub use camera::{CameraSource, PocketCamera, CAMERA_HEIGHT, CAMERA_WIDTH};
+pub use five::Mbc5;
+pub use header::CartridgeHeader;
+pub use huc1::HuC1;
+pub use huc3::{HuC3, HuC3Clock};
+pub use mmm01::Mmm01;
 pub use none::NoMbc;
 pub use one::Mbc1;
+pub use seven::{Eeprom, EepromState, Mbc7};
+pub use three::{Mbc3, Rtc};
+pub use two::Mbc2;
 
 /// MBC kinds, used to set which kind the CPU will use
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum MbcSelector {
-    /// 16KiB ROM, no RAM
-    NoMbc,
-    /// Max 2MiB ROM, 32KiB RAM
-    Mbc1(RomSize, RamSize),
+    /// 32KiB ROM, up to 8KiB RAM, battery backed if the last field is `true`
+    NoMbc(RamSize, bool),
+    /// Max 2MiB ROM, 32KiB RAM, battery backed if the last field is `true`
+    Mbc1(RomSize, RamSize, bool),
+    /// Max 256KiB ROM, 512 half-bytes of built-in RAM, battery backed if the last field is `true`
+    Mbc2(RomSize, bool),
+    /// Max 2MiB ROM, 32KiB RAM, with an optional real-time clock
+    Mbc3(RomSize, RamSize, Mbc3Features),
+    /// Max 8MiB ROM, 128KiB RAM, with an optional rumble motor
+    Mbc5(RomSize, RamSize, Mbc5Features),
+    /// Max 8MiB ROM, 128KiB RAM, battery backed if the last field is `true`
+    ///
+    /// Used by multi-game collections, with a menu in the last 32KiB of ROM that locks in one of the games
+    Mmm01(RomSize, RamSize, bool),
+    /// Max 2MiB ROM, 256 byte EEPROM instead of RAM, with an accelerometer
+    Mbc7(RomSize),
+    /// Max 1MiB ROM, 128KiB RAM, always battery backed, with a camera sensor
+    PocketCamera(RomSize),
+    /// Max 1MiB ROM, 32KiB RAM, always battery backed, with an IR port
+    HuC1(RomSize, RamSize),
+    /// Max 2MiB ROM, 32KiB RAM, always battery backed, with a clock and an IR port
+    HuC3(RomSize, RamSize),
+}
+
+/// Optional hardware on an MBC3 cartridge
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Mbc3Features {
+    /// Has a real-time clock
+    pub timer: bool,
+    /// RAM, and the clock if there is one, are battery backed
+    pub battery: bool,
 }
 
-#[derive(Clone, Copy, Debug)]
+/// Optional hardware on an MBC5 cartridge
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Mbc5Features {
+    /// Has a rumble motor
+    pub rumble: bool,
+    /// RAM is battery backed
+    pub battery: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveError {
+    /// The cartridge has nothing to persist
+    NoBattery,
+    /// The save data doesn't match the size of the cartridge RAM
+    InvalidSize { expected: usize, found: usize },
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::NoBattery => write!(f, "Cartridge has no battery backed memory"),
+            SaveError::InvalidSize { expected, found } => {
+                write!(f, "Save data is {found} bytes, expected {expected}")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RomLoadError {
+    /// The cartridge type byte at $0147 names a mapper that isn't emulated
+    UnsupportedMapper(u8),
+    /// The ROM size byte at $0148 isn't a known size
+    InvalidRomSize(u8),
+    /// The RAM size byte at $0149 isn't a known size
+    InvalidRamSize(u8),
+    /// The image is shorter than its header or the size declared in it
+    Truncated { expected: usize, found: usize },
+    /// The image doesn't fit in the cartridge ROM
+    Oversized { max: usize, found: usize },
+    /// The header declares a ROM or RAM size the mapper can't address
+    HeaderMismatch(MbcSelector),
+    /// The boot ROM is neither the 256 byte DMG boot ROM nor the 2304 byte CGB boot ROM
+    InvalidBootRomSize(usize),
+}
+
+impl fmt::Display for RomLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomLoadError::UnsupportedMapper(code) => write!(f, "Unsupported MBC ({code:#04X})"),
+            RomLoadError::InvalidRomSize(byte) => write!(f, "Unsupported ROM size ({byte:#04X})"),
+            RomLoadError::InvalidRamSize(byte) => write!(f, "Unsupported RAM size ({byte:#04X})"),
+            RomLoadError::Truncated { expected, found } => {
+                write!(f, "ROM is {found} bytes, expected {expected}")
+            }
+            RomLoadError::Oversized { max, found } => {
+                write!(f, "ROM is {found} bytes, cartridge only holds {max}")
+            }
+            RomLoadError::HeaderMismatch(kind) => {
+                write!(f, "Invalid ROM or RAM size for cartridge ({kind:?})")
+            }
+            RomLoadError::InvalidBootRomSize(len) => {
+                write!(f, "Boot ROM is {len} bytes, expected 256 or 2304")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum RomSize {
     /// 2 banks, 32KiB
     Zero,
@@ -35,7 +153,7 @@ pub enum RomSize {
     Eight,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum RamSize {
     /// 0 banks
     Zero,
@@ -57,31 +175,55 @@ pub enum MbcAddr {
 }
 
 impl RomSize {
+    /// Decodes the ROM size byte at $0148
+    ///
+    /// ### Panic Conditions
+    /// - Panics if `byte` isn't a known size, see `RomSize::try_from()`
     pub fn from_byte(byte: u8) -> Self {
+        Self::try_from(byte).unwrap_or_else(|e| panic!("{e}"))
+    }
+}
+
+impl TryFrom<u8> for RomSize {
+    type Error = RomLoadError;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
         match byte {
-            0x00 => Self::Zero,
-            0x01 => Self::One,
-            0x02 => Self::Two,
-            0x03 => Self::Three,
-            0x04 => Self::Four,
-            0x05 => Self::Five,
-            0x06 => Self::Six,
-            0x07 => Self::Seven,
-            0x08 => Self::Eight,
-            _ => panic!("Unsupported ROM size"),
+            0x00 => Ok(Self::Zero),
+            0x01 => Ok(Self::One),
+            0x02 => Ok(Self::Two),
+            0x03 => Ok(Self::Three),
+            0x04 => Ok(Self::Four),
+            0x05 => Ok(Self::Five),
+            0x06 => Ok(Self::Six),
+            0x07 => Ok(Self::Seven),
+            0x08 => Ok(Self::Eight),
+            _ => Err(RomLoadError::InvalidRomSize(byte)),
         }
     }
 }
 
 impl RamSize {
+    /// Decodes the RAM size byte at $0149
+    ///
+    /// ### Panic Conditions
+    /// - Panics if `byte` isn't a known size, see `RamSize::try_from()`
     pub fn from_byte(byte: u8) -> Self {
+        Self::try_from(byte).unwrap_or_else(|e| panic!("{e}"))
+    }
+}
+
+impl TryFrom<u8> for RamSize {
+    type Error = RomLoadError;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
         match byte {
-            0x00 => Self::Zero,
-            0x02 => Self::Two,
-            0x03 => Self::Three,
-            0x04 => Self::Four,
-            0x05 => Self::Five,
-            _ => panic!("Unsupported RAM size"),
+            0x00 => Ok(Self::Zero),
+            0x02 => Ok(Self::Two),
+            0x03 => Ok(Self::Three),
+            0x04 => Ok(Self::Four),
+            0x05 => Ok(Self::Five),
+            _ => Err(RomLoadError::InvalidRamSize(byte)),
         }
     }
 }
@@ -94,7 +236,19 @@ pub trait Mbc: Send + Sync {
     fn set(&mut self, addr: u16, value: u8);
 
     /// Loads cartridge data into ROM
-    fn load_rom(&mut self, data: &[u8]);
+    ///
+    /// ### Return Variants
+    /// - `Ok(())` if the data fit in the cartridge ROM
+    /// - `Err(RomLoadError::Oversized)` if it didn't
+    fn try_load_rom(&mut self, data: &[u8]) -> Result<(), RomLoadError>;
+
+    /// Loads cartridge data into ROM
+    ///
+    /// ### Panic Conditions
+    /// - Panics if the data doesn't fit in the cartridge ROM, see `Mbc::try_load_rom()`
+    fn load_rom(&mut self, data: &[u8]) {
+        self.try_load_rom(data).unwrap_or_else(|e| panic!("{e}"));
+    }
 
     /// Translates a global memory address into an internal MBC address of either the ROM or RAM section
     ///
@@ -103,28 +257,93 @@ pub trait Mbc: Send + Sync {
     /// ### Panic Conditions
     /// - This should panic if `addr` is not within the bounds of the MBC
     fn translate(&self, addr: u16) -> MbcAddr;
+
+    /// Returns the ROM bank currently mapped into 4000-7FFF
+    fn rom_bank(&self) -> usize;
+
+    /// Gets the byte at `addr` in ROM bank `bank`, whether or not the bank is mapped
+    ///
+    /// Only the low 14 bits of `addr` are used, returns `None` if the bank doesn't exist
+    fn load_rom_bank(&self, bank: usize, addr: u16) -> Option<u8>;
+
+    /// Returns whether the cartridge's rumble motor is currently on
+    ///
+    /// Always `false` for cartridges without a rumble motor
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    /// Returns whether the cartridge has a battery keeping its memory alive while powered off
+    fn has_battery(&self) -> bool {
+        false
+    }
+
+    /// Dumps battery backed memory in the raw `.sav` layout, with RAM banks back to back
+    ///
+    /// Cartridges with a clock append the 48 byte RTC footer used by most emulators
+    fn dump_ram(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restores battery backed memory from the raw `.sav` layout produced by `Mbc::dump_ram()`
+    fn restore_ram(&mut self, _data: &[u8]) -> Result<(), SaveError> {
+        Err(SaveError::NoBattery)
+    }
+
+    /// Returns whether the cartridge's infrared LED is currently lit
+    ///
+    /// Always `false` for cartridges without an IR port
+    fn ir_led(&self) -> bool {
+        false
+    }
+
+    /// Sets whether the cartridge's infrared receiver is currently seeing light
+    fn set_ir_input(&mut self, _light: bool) {}
+
+    /// Sets the tilt seen by the cartridge's accelerometer in g, ignored by cartridges without one
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+
+    /// Sets where the cartridge's camera sensor gets its images from, ignored by cartridges without one
+    fn set_camera_source(&mut self, _source: Box<dyn CameraSource>) {}
 }
 
+/// Builds the MBC described by `kind`
+///
+/// ### Panic Conditions
+/// - Panics if the ROM or RAM size can't be used with the MBC, see `try_init_mbc()`
 pub fn init_mbc(kind: MbcSelector) -> Box<dyn Mbc> {
-    match kind {
-        MbcSelector::NoMbc => Box::new(NoMbc {
-            rom: Box::new([None; 0x8000]),
-            ram: Box::new([None; 0x2000]),
-        }),
-        MbcSelector::Mbc1(rom_size, ram_size) => {
+    try_init_mbc(kind).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// Builds the MBC described by `kind`
+///
+/// ### Return Variants
+/// - `Ok(mbc)` with blank ROM and RAM banks
+/// - `Err(RomLoadError::HeaderMismatch)` if the ROM or RAM size can't be used with the MBC
+pub fn try_init_mbc(kind: MbcSelector) -> Result<Box<dyn Mbc>, RomLoadError> {
+    let mbc: Box<dyn Mbc> = match kind {
+        MbcSelector::NoMbc(ram_size, battery) => {
+            let ram_banks = match ram_size {
+                RamSize::Zero | RamSize::Two => convert_ram_size(&ram_size),
+                _ => return Err(RomLoadError::HeaderMismatch(kind)),
+            };
+
+            let ram = vec![Box::new([None; 0x2000]); ram_banks];
+
+            Box::new(NoMbc {
+                rom: Box::new([None; 0x8000]),
+                ram: ram.into_boxed_slice(),
+                battery,
+            })
+        }
+        MbcSelector::Mbc1(rom_size, ram_size, battery) => {
             let rom_banks = match rom_size {
-                RomSize::Seven | RomSize::Eight => {
-                    let banks = convert_rom_size(&rom_size);
-                    panic!("Invalid ROM size for MBC1 ({banks} banks)");
-                }
+                RomSize::Seven | RomSize::Eight => return Err(RomLoadError::HeaderMismatch(kind)),
                 size => convert_rom_size(&size),
             };
 
             let ram_banks = match ram_size {
-                RamSize::Four | RamSize::Five => {
-                    let banks = convert_ram_size(&ram_size);
-                    panic!("Invalid RAM size for MBC1 ({banks} banks)");
-                }
+                RamSize::Four | RamSize::Five => return Err(RomLoadError::HeaderMismatch(kind)),
                 size => convert_ram_size(&size),
             };
 
@@ -132,15 +351,262 @@ pub fn init_mbc(kind: MbcSelector) -> Box<dyn Mbc> {
             let ram = vec![Box::new([None; 0x2000]); ram_banks];
 
             Box::new(Mbc1 {
+                rom: rom.into_boxed_slice(),
+                ram: ram.into_boxed_slice(),
+                bank1: 1,
+                bank2: 0,
+                ram_enabled: false,
+                mode: false,
+                multicart: false,
+                battery,
+            })
+        }
+        MbcSelector::Mbc2(rom_size, battery) => {
+            let rom_banks = match rom_size {
+                RomSize::Zero | RomSize::One | RomSize::Two | RomSize::Three => {
+                    convert_rom_size(&rom_size)
+                }
+                _ => return Err(RomLoadError::HeaderMismatch(kind)),
+            };
+
+            let rom = vec![Box::new([None; 0x4000]); rom_banks];
+
+            Box::new(Mbc2 {
+                rom: rom.into_boxed_slice(),
+                ram: Box::new([None; 0x200]),
+                rom_bank: 1,
+                ram_enabled: false,
+                battery,
+            })
+        }
+        MbcSelector::Mbc3(rom_size, ram_size, Mbc3Features { timer, battery }) => {
+            let rom_banks = match rom_size {
+                RomSize::Seven | RomSize::Eight => return Err(RomLoadError::HeaderMismatch(kind)),
+                size => convert_rom_size(&size),
+            };
+
+            let ram_banks = match ram_size {
+                RamSize::Four | RamSize::Five => return Err(RomLoadError::HeaderMismatch(kind)),
+                size => convert_ram_size(&size),
+            };
+
+            let rom = vec![Box::new([None; 0x4000]); rom_banks];
+            let ram = vec![Box::new([None; 0x2000]); ram_banks];
+
+            Box::new(Mbc3 {
+                rom: rom.into_boxed_slice(),
+                ram: ram.into_boxed_slice(),
+                rom_bank: 1,
+                ram_bank: 0,
+                ram_enabled: false,
+                latch_ready: false,
+                rtc: if timer { Some(Rtc::new()) } else { None },
+                battery,
+            })
+        }
+        MbcSelector::Mbc5(rom_size, ram_size, Mbc5Features { rumble: has_rumble, battery }) => {
+            let rom_banks = convert_rom_size(&rom_size);
+            let ram_banks = convert_ram_size(&ram_size);
+
+            let rom = vec![Box::new([None; 0x4000]); rom_banks];
+            let ram = vec![Box::new([None; 0x2000]); ram_banks];
+
+            Box::new(Mbc5 {
                 rom: rom.into_boxed_slice(),
                 ram: ram.into_boxed_slice(),
                 rom_bank: 1,
                 ram_bank: 0,
-                ram_banking: false,
                 ram_enabled: false,
+                has_rumble,
+                rumble: false,
+                battery,
             })
         }
+        MbcSelector::Mmm01(rom_size, ram_size, battery) => {
+            let rom_banks = convert_rom_size(&rom_size);
+            let ram_banks = convert_ram_size(&ram_size);
+
+            let rom = vec![Box::new([None; 0x4000]); rom_banks];
+            let ram = vec![Box::new([None; 0x2000]); ram_banks];
+
+            Box::new(Mmm01 {
+                rom: rom.into_boxed_slice(),
+                ram: ram.into_boxed_slice(),
+                locked: false,
+                rom_bank_low: 0,
+                rom_bank_mid: 0,
+                rom_bank_high: 0,
+                rom_bank_mask: 0,
+                ram_bank_low: 0,
+                ram_bank_high: 0,
+                ram_bank_mask: 0,
+                ram_enabled: false,
+                mode: false,
+                mode_locked: false,
+                battery,
+            })
+        }
+        MbcSelector::Mbc7(rom_size) => {
+            let rom_banks = match rom_size {
+                RomSize::Seven | RomSize::Eight => return Err(RomLoadError::HeaderMismatch(kind)),
+                size => convert_rom_size(&size),
+            };
+
+            let rom = vec![Box::new([None; 0x4000]); rom_banks];
+
+            Box::new(Mbc7 {
+                rom: rom.into_boxed_slice(),
+                rom_bank: 1,
+                ram_enabled: false,
+                ram_enabled_2: false,
+                x: 0x8000,
+                y: 0x8000,
+                latch_ready: false,
+                tilt: (0.0, 0.0),
+                eeprom: Eeprom::new(),
+            })
+        }
+        MbcSelector::PocketCamera(rom_size) => {
+            let rom_banks = match rom_size {
+                RomSize::Six | RomSize::Seven | RomSize::Eight => {
+                    return Err(RomLoadError::HeaderMismatch(kind))
+                }
+                size => convert_rom_size(&size),
+            };
+
+            let rom = vec![Box::new([None; 0x4000]); rom_banks];
+            let ram = vec![Box::new([None; 0x2000]); 16];
+
+            Box::new(PocketCamera {
+                rom: rom.into_boxed_slice(),
+                ram: ram.into_boxed_slice(),
+                rom_bank: 1,
+                ram_bank: 0,
+                ram_enabled: false,
+                registers: [0; 0x36],
+                source: None,
+            })
+        }
+        MbcSelector::HuC1(rom_size, ram_size) => {
+            let rom_banks = match rom_size {
+                RomSize::Six | RomSize::Seven | RomSize::Eight => {
+                    return Err(RomLoadError::HeaderMismatch(kind))
+                }
+                size => convert_rom_size(&size),
+            };
+
+            let ram_banks = match ram_size {
+                RamSize::Four | RamSize::Five => return Err(RomLoadError::HeaderMismatch(kind)),
+                size => convert_ram_size(&size),
+            };
+
+            let rom = vec![Box::new([None; 0x4000]); rom_banks];
+            let ram = vec![Box::new([None; 0x2000]); ram_banks];
+
+            Box::new(HuC1 {
+                rom: rom.into_boxed_slice(),
+                ram: ram.into_boxed_slice(),
+                rom_bank: 1,
+                ram_bank: 0,
+                ir_mode: false,
+                ir_led: false,
+                ir_input: false,
+            })
+        }
+        MbcSelector::HuC3(rom_size, ram_size) => {
+            let rom_banks = match rom_size {
+                RomSize::Seven | RomSize::Eight => return Err(RomLoadError::HeaderMismatch(kind)),
+                size => convert_rom_size(&size),
+            };
+
+            let ram_banks = match ram_size {
+                RamSize::Four | RamSize::Five => return Err(RomLoadError::HeaderMismatch(kind)),
+                size => convert_ram_size(&size),
+            };
+
+            let rom = vec![Box::new([None; 0x4000]); rom_banks];
+            let ram = vec![Box::new([None; 0x2000]); ram_banks];
+
+            Box::new(HuC3 {
+                rom: rom.into_boxed_slice(),
+                ram: ram.into_boxed_slice(),
+                rom_bank: 1,
+                ram_bank: 0,
+                mode: 0,
+                clock: HuC3Clock::new(),
+                ir_led: false,
+                ir_input: false,
+            })
+        }
+    };
+
+    Ok(mbc)
+}
+
+/// Flattens RAM banks into the raw `.sav` layout, with uninitialized cells saved as 0xFF
+pub(crate) fn dump_banks<const N: usize>(banks: &[Box<[Option<u8>; N]>]) -> Vec<u8> {
+    banks
+        .iter()
+        .flat_map(|bank| bank.iter().map(|cell| cell.unwrap_or(0xFF)))
+        .collect()
+}
+
+/// Fills RAM banks from the raw `.sav` layout
+///
+/// ### Return Variants
+/// - `Ok(())` if `data` was exactly the size of the banks
+/// - `Err(SaveError::InvalidSize)` otherwise, leaving the banks untouched
+pub(crate) fn restore_banks<const N: usize>(
+    banks: &mut [Box<[Option<u8>; N]>],
+    data: &[u8],
+) -> Result<(), SaveError> {
+    let expected = banks.len() * N;
+    if data.len() != expected {
+        return Err(SaveError::InvalidSize { expected, found: data.len() });
+    }
+
+    for (bank, chunk) in banks.iter_mut().zip(data.chunks(N)) {
+        for (cell, byte) in bank.iter_mut().zip(chunk) {
+            *cell = Some(*byte);
+        }
     }
+
+    Ok(())
+}
+
+/// Gets the byte at `addr` in ROM bank `bank`, see `Mbc::load_rom_bank()`
+pub(crate) fn load_rom_bank(banks: &[Box<[Option<u8>; 0x4000]>], bank: usize, addr: u16) -> Option<u8> {
+    banks.get(bank).and_then(|bank| bank[(addr & 0x3FFF) as usize])
+}
+
+/// Copies a ROM image into 16KiB banks, leaving cells past the end of `data` uninitialized
+///
+/// ### Return Variants
+/// - `Ok(())` if `data` fit in the banks
+/// - `Err(RomLoadError::Oversized)` otherwise, leaving the banks untouched
+pub(crate) fn load_banks(
+    banks: &mut [Box<[Option<u8>; 0x4000]>],
+    data: &[u8],
+) -> Result<(), RomLoadError> {
+    let max = banks.len() * 0x4000;
+    if data.len() > max {
+        return Err(RomLoadError::Oversized { max, found: data.len() });
+    }
+
+    for (bank, chunk) in banks.iter_mut().zip(data.chunks(0x4000)) {
+        for (cell, byte) in bank.iter_mut().zip(chunk) {
+            *cell = Some(*byte);
+        }
+    }
+
+    Ok(())
+}
+
+/// Current Unix time in seconds, used as the time base for cartridge clocks
+pub(crate) fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
 }
 
 fn convert_rom_size(size: &RomSize) -> usize {
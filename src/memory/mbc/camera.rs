@@ -0,0 +1,247 @@
+use super::{dump_banks, load_banks, load_rom_bank, restore_banks, Mbc, MbcAddr, RomLoadError, SaveError};
+
+/// Width of a capture in pixels
+pub const CAMERA_WIDTH: usize = 128;
+/// Height of a capture in pixels
+pub const CAMERA_HEIGHT: usize = 112;
+
+/// RAM bank select value that maps the sensor registers into A000-BFFF
+const REGISTER_BANK: u8 = 0x10;
+/// Number of sensor registers, echoed every 0x80 bytes
+const REGISTER_COUNT: usize = 0x36;
+/// Start of the 4x4 dither matrix in the registers, 3 thresholds per pixel
+const DITHER_MATRIX: usize = 0x06;
+/// Where captures are written to in RAM bank 0
+const CAPTURE_START: usize = 0x0100;
+
+/// Edge enhancement ratios selected by bits 4-6 of register 4
+const EDGE_RATIOS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+
+/// A grayscale frame source for the camera sensor, such as a webcam or a static image
+pub trait CameraSource: Send + Sync {
+    /// Fills `frame` with a 128x112 image row by row, where 0 is black and 255 is white
+    fn capture(&mut self, frame: &mut [u8; CAMERA_WIDTH * CAMERA_HEIGHT]);
+}
+
+pub struct PocketCamera {
+    /// Cartridge ROM, up to 64 banks, each 16384 bytes
+    pub rom: Box<[Box<[Option<u8>; 0x4000]>]>,
+    /// Cartridge RAM, 16 banks, each 8192 bytes
+    pub ram: Box<[Box<[Option<u8>; 0x2000]>]>,
+    pub rom_bank: u8,
+    /// Either a RAM bank (0x00-0x0F) or the sensor registers (0x10)
+    pub ram_bank: u8,
+    pub ram_enabled: bool,
+    /// Sensor registers A000-A035
+    pub registers: [u8; REGISTER_COUNT],
+    /// Where captures come from, without one the sensor sees a flat gray image
+    pub source: Option<Box<dyn CameraSource>>,
+}
+
+impl PocketCamera {
+    /// Captures a frame from the source and writes it to RAM as 2bpp tiles, the same way the sensor and its
+    /// processing would
+    ///
+    /// This is an approximation of the analog pipeline: the exposure scales the image, optional edge enhancement
+    /// is applied, and the result is quantized to 4 shades with the dither matrix
+    pub fn capture(&mut self) {
+        let mut frame = [0x80; CAMERA_WIDTH * CAMERA_HEIGHT];
+        if let Some(source) = self.source.as_mut() {
+            source.capture(&mut frame);
+        }
+
+        let exposure = u16::from_be_bytes([self.registers[2], self.registers[3]]) as f32;
+        let exposed = frame.map(|pixel| pixel as f32 * exposure / 0x1000 as f32);
+
+        let mode = (self.registers[1] >> 5) & 0b11;
+        let ratio = EDGE_RATIOS[((self.registers[4] >> 4) & 0b111) as usize];
+        let invert = self.registers[4] & 0b1000 > 0;
+
+        let mut tiles = [0u8; CAMERA_WIDTH * CAMERA_HEIGHT / 4];
+
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let at = |x: usize, y: usize| exposed[y * CAMERA_WIDTH + x];
+                let center = at(x, y);
+
+                // VH bits pick which neighbours are used to sharpen the image
+                let mut edge = 0.0;
+                if mode & 0b01 > 0 {
+                    edge += 2.0 * center
+                        - at(x.saturating_sub(1), y)
+                        - at((x + 1).min(CAMERA_WIDTH - 1), y);
+                }
+                if mode & 0b10 > 0 {
+                    edge += 2.0 * center
+                        - at(x, y.saturating_sub(1))
+                        - at(x, (y + 1).min(CAMERA_HEIGHT - 1));
+                }
+
+                let value = (center + edge * ratio).clamp(0.0, 255.0) as u8;
+                let value = if invert { 255 - value } else { value };
+
+                let matrix = DITHER_MATRIX + ((y & 3) * 4 + (x & 3)) * 3;
+                let thresholds = &self.registers[matrix..matrix + 3];
+
+                let shade = if value < thresholds[0] {
+                    3
+                } else if value < thresholds[1] {
+                    2
+                } else if value < thresholds[2] {
+                    1
+                } else {
+                    0
+                };
+
+                let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+                let row = tile * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+
+                tiles[row] |= (shade & 1) << bit;
+                tiles[row + 1] |= (shade >> 1) << bit;
+            }
+        }
+
+        for (cell, byte) in self.ram[0][CAPTURE_START..].iter_mut().zip(tiles) {
+            *cell = Some(byte);
+        }
+    }
+}
+
+impl Mbc for PocketCamera {
+    fn load(&self, addr: u16) -> Option<u8> {
+        let addr = self.translate(addr);
+
+        match addr {
+            MbcAddr::Rom0(a) => self.rom[0][a as usize],
+            MbcAddr::RomX(a) => self.rom[self.rom_bank as usize][a as usize],
+            // only A000 can be read back, and captures finish instantly so it's never busy
+            MbcAddr::Ram(a) if self.ram_bank & REGISTER_BANK > 0 => {
+                if a & 0x7F == 0 {
+                    Some(self.registers[0] & !1)
+                } else {
+                    Some(0x00)
+                }
+            }
+            MbcAddr::Ram(a) => self.ram[(self.ram_bank & 0x0F) as usize][a as usize],
+        }
+    }
+
+    fn set(&mut self, addr: u16, value: u8) {
+        match self.translate(addr) {
+            MbcAddr::Rom0(_) | MbcAddr::RomX(_) => match addr {
+                0x0000..=0x1FFF => {
+                    self.ram_enabled = value & 0x0F == 0x0A;
+                }
+                0x2000..=0x3FFF => {
+                    self.rom_bank = value & 0x3F & (self.rom.len() - 1) as u8;
+                }
+                0x4000..=0x5FFF => {
+                    self.ram_bank = value & 0x1F;
+                }
+                0x6000..=0x7FFF => {}
+                _ => unreachable!(),
+            },
+            // the registers can be written even while RAM is disabled
+            MbcAddr::Ram(a) if self.ram_bank & REGISTER_BANK > 0 => {
+                let reg = (a & 0x7F) as usize;
+                if reg < REGISTER_COUNT {
+                    self.registers[reg] = value;
+                }
+
+                if reg == 0 && value & 1 > 0 {
+                    self.capture();
+                }
+            }
+            MbcAddr::Ram(a) => {
+                if self.ram_enabled {
+                    self.ram[(self.ram_bank & 0x0F) as usize][a as usize] = Some(value);
+                }
+            }
+        }
+    }
+
+    fn try_load_rom(&mut self, data: &[u8]) -> Result<(), RomLoadError> {
+        load_banks(&mut self.rom, data)
+    }
+
+    fn translate(&self, addr: u16) -> MbcAddr {
+        match addr {
+            0x0000..=0x3FFF => MbcAddr::Rom0(addr),
+            0x4000..=0x7FFF => MbcAddr::RomX(addr - 0x4000),
+            0xA000..=0xBFFF => MbcAddr::Ram(addr - 0xA000),
+            _ => panic!("Invalid memory translation: ${addr:#06x}"),
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+
+    fn load_rom_bank(&self, bank: usize, addr: u16) -> Option<u8> {
+        load_rom_bank(&self.rom, bank, addr)
+    }
+
+    fn has_battery(&self) -> bool {
+        true
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        dump_banks(&self.ram)
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) -> Result<(), SaveError> {
+        restore_banks(&mut self.ram, data)
+    }
+
+    fn set_camera_source(&mut self, source: Box<dyn CameraSource>) {
+        self.source = Some(source);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::mbc::{init_mbc, MbcSelector, RomSize};
+
+    use super::{CameraSource, CAMERA_HEIGHT, CAMERA_WIDTH};
+
+    /// Left half black, right half white
+    struct Split;
+
+    impl CameraSource for Split {
+        fn capture(&mut self, frame: &mut [u8; CAMERA_WIDTH * CAMERA_HEIGHT]) {
+            for (i, pixel) in frame.iter_mut().enumerate() {
+                *pixel = if i % CAMERA_WIDTH < CAMERA_WIDTH / 2 {
+                    0x00
+                } else {
+                    0xFF
+                };
+            }
+        }
+    }
+
+    #[test]
+    fn capture() {
+        let mut mbc = init_mbc(MbcSelector::PocketCamera(RomSize::Zero));
+        mbc.set_camera_source(Box::new(Split));
+
+        mbc.set(0x4000, 0x10);
+        // exposure of 0x1000 leaves the image as is
+        mbc.set(0xA002, 0x10);
+        mbc.set(0xA003, 0x00);
+        for i in 0..16 {
+            mbc.set(0xA006 + i * 3, 0x40);
+            mbc.set(0xA007 + i * 3, 0x80);
+            mbc.set(0xA008 + i * 3, 0xC0);
+        }
+        mbc.set(0xA000, 0x01);
+        assert_eq!(mbc.load(0xA000), Some(0x00));
+
+        mbc.set(0x4000, 0x00);
+        // first tile is black, the first tile of the right half is white
+        assert_eq!(mbc.load(0xA100), Some(0xFF));
+        assert_eq!(mbc.load(0xA101), Some(0xFF));
+        assert_eq!(mbc.load(0xA100 + 8 * 16), Some(0x00));
+        assert_eq!(mbc.load(0xA101 + 8 * 16), Some(0x00));
+    }
+}
@@ -0,0 +1,149 @@
+use super::{dump_banks, load_banks, load_rom_bank, restore_banks, Mbc, MbcAddr, RomLoadError, SaveError};
+
+#[derive(Clone)]
+pub struct Mbc5 {
+    /// Cartridge ROM, up to 512 banks, each 16384 bytes
+    pub rom: Box<[Box<[Option<u8>; 0x4000]>]>,
+    /// Cartridge RAM, up to 16 banks, each 8192 bytes
+    pub ram: Box<[Box<[Option<u8>; 0x2000]>]>,
+    /// 9 bit ROM bank number, unlike the other MBCs bank 0 can be mapped into 4000-7FFF
+    pub rom_bank: u16,
+    pub ram_bank: u8,
+    pub ram_enabled: bool,
+    /// Whether the cartridge has a rumble motor, which takes over bit 3 of the RAM bank register
+    pub has_rumble: bool,
+    /// Whether the rumble motor is currently on
+    pub rumble: bool,
+    pub battery: bool,
+}
+
+impl Mbc for Mbc5 {
+    fn load(&self, addr: u16) -> Option<u8> {
+        let addr = self.translate(addr);
+
+        match addr {
+            MbcAddr::Rom0(a) => self.rom[0][a as usize],
+            MbcAddr::RomX(a) => self.rom[self.rom_bank as usize][a as usize],
+            MbcAddr::Ram(a) => {
+                if self.ram_enabled {
+                    self.ram
+                        .get(self.ram_bank as usize)
+                        .map_or(Some(0xFF), |bank| bank[a as usize])
+                } else {
+                    Some(0xFF)
+                }
+            }
+        }
+    }
+
+    fn set(&mut self, addr: u16, value: u8) {
+        match self.translate(addr) {
+            MbcAddr::Rom0(_) | MbcAddr::RomX(_) => match addr {
+                0x0000..=0x1FFF => {
+                    self.ram_enabled = value & 0x0F == 0x0A;
+                }
+                0x2000..=0x2FFF => {
+                    // lower 8 bits of the rom bank number
+                    let bank = (self.rom_bank & 0x100) | value as u16;
+                    self.rom_bank = bank & (self.rom.len() - 1) as u16;
+                }
+                0x3000..=0x3FFF => {
+                    // bit 8 of the rom bank number
+                    let bank = (self.rom_bank & 0xFF) | ((value as u16 & 1) << 8);
+                    self.rom_bank = bank & (self.rom.len() - 1) as u16;
+                }
+                0x4000..=0x5FFF => {
+                    if self.has_rumble {
+                        self.rumble = value & 0b1000 > 0;
+                        self.ram_bank = value & 0b0111;
+                    } else {
+                        self.ram_bank = value & 0x0F;
+                    }
+                }
+                0x6000..=0x7FFF => {}
+                _ => unreachable!(),
+            },
+            MbcAddr::Ram(a) => {
+                if self.ram_enabled {
+                    if let Some(bank) = self.ram.get_mut(self.ram_bank as usize) {
+                        bank[a as usize] = Some(value);
+                    }
+                }
+            }
+        }
+    }
+
+    fn try_load_rom(&mut self, data: &[u8]) -> Result<(), RomLoadError> {
+        load_banks(&mut self.rom, data)
+    }
+
+    fn translate(&self, addr: u16) -> MbcAddr {
+        match addr {
+            0x0000..=0x3FFF => MbcAddr::Rom0(addr),
+            0x4000..=0x7FFF => MbcAddr::RomX(addr - 0x4000),
+            0xA000..=0xBFFF => MbcAddr::Ram(addr - 0xA000),
+            _ => panic!("Invalid memory translation: ${addr:#06x}"),
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+
+    fn load_rom_bank(&self, bank: usize, addr: u16) -> Option<u8> {
+        load_rom_bank(&self.rom, bank, addr)
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        dump_banks(&self.ram)
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) -> Result<(), SaveError> {
+        restore_banks(&mut self.ram, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::mbc::{init_mbc, Mbc5Features, MbcSelector, RamSize, RomSize};
+
+    #[test]
+    fn rom_bank_high_bit() {
+        let mut mbc = init_mbc(MbcSelector::Mbc5(RomSize::Eight, RamSize::Zero, Mbc5Features::default()));
+        let mut rom = vec![0; 0x4000 * 512];
+        rom[0x4000 * 0x101] = 0x45;
+        mbc.load_rom(&rom);
+
+        mbc.set(0x2000, 0x01);
+        mbc.set(0x3000, 0x01);
+        assert_eq!(mbc.load(0x4000), Some(0x45));
+
+        // bank 0 can be mapped to the switchable area
+        mbc.set(0x2000, 0x00);
+        mbc.set(0x3000, 0x00);
+        assert_eq!(mbc.load(0x4000), Some(0x00));
+    }
+
+    #[test]
+    fn rumble() {
+        let mut mbc = init_mbc(MbcSelector::Mbc5(RomSize::Zero, RamSize::Four, Mbc5Features { rumble: true, battery: false }));
+        mbc.set(0x0000, 0x0A);
+
+        mbc.set(0x4000, 0b1010);
+        assert!(mbc.rumble());
+
+        // the motor bit doesn't select a ram bank
+        mbc.set(0xA000, 0x45);
+        mbc.set(0x4000, 0b0010);
+        assert!(!mbc.rumble());
+        assert_eq!(mbc.load(0xA000), Some(0x45));
+    }
+}
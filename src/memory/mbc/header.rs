@@ -0,0 +1,463 @@
+use crate::MBC_ADDR;
+
+use super::{convert_rom_size, Mbc3Features, Mbc5Features, MbcSelector, RamSize, RomLoadError, RomSize};
+
+/// Start of the Nintendo logo
+pub const LOGO: usize = 0x0104;
+/// Start of the title
+const TITLE: usize = 0x0134;
+/// Start of the manufacturer code, which overlaps the end of the title on older cartridges
+const MANUFACTURER_CODE: usize = 0x013F;
+/// CGB support flag, which overlaps the last character of the title on older cartridges
+const CGB_FLAG: usize = 0x0143;
+/// Two character licensee code, only used if the old licensee code is 0x33
+const NEW_LICENSEE: usize = 0x0144;
+/// SGB support flag
+const SGB_FLAG: usize = 0x0146;
+/// ROM size
+const ROM_SIZE: usize = 0x0148;
+/// RAM size
+const RAM_SIZE: usize = 0x0149;
+/// Destination code
+const DESTINATION: usize = 0x014A;
+/// Single byte licensee code
+const OLD_LICENSEE: usize = 0x014B;
+/// Mask ROM version number
+const VERSION: usize = 0x014C;
+/// Checksum over 0134-014C
+const HEADER_CHECKSUM: usize = 0x014D;
+/// Big endian checksum over the whole ROM, excluding itself
+const GLOBAL_CHECKSUM: usize = 0x014E;
+/// End of the header, exclusive
+pub const HEADER_END: usize = 0x0150;
+
+/// Old licensee value that means the new licensee code should be used instead
+const USE_NEW_LICENSEE: u8 = 0x33;
+
+/// The logo the boot ROM checks for at $0104, every licensed cartridge has a copy
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+/// Cartridge metadata, parsed from 0100-014F
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub title: String,
+    /// Only present on newer cartridges, which use part of the title area for it
+    pub manufacturer_code: Option<String>,
+    pub cgb_flag: CgbFlag,
+    pub new_licensee: String,
+    pub old_licensee: u8,
+    pub sgb: bool,
+    pub cartridge_type: CartridgeType,
+    pub rom_size: RomSize,
+    pub ram_size: RamSize,
+    pub destination: Destination,
+    pub version: u8,
+    pub header_checksum: u8,
+    /// Whether `header_checksum` matches the header. The boot ROM refuses to run the cartridge if this is `false`
+    pub header_checksum_valid: bool,
+    pub global_checksum: u16,
+    /// Whether `global_checksum` matches the ROM. Real hardware never checks this
+    pub global_checksum_valid: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CgbFlag {
+    /// Made for DMG only
+    Dmg,
+    /// Supports CGB functions, but still works on DMG
+    Enhanced,
+    /// Only works on CGB
+    Only,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mapper {
+    None,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    Mmm01,
+    PocketCamera,
+    Tama5,
+    HuC1,
+    HuC3,
+    /// A cartridge type byte that isn't in any known list
+    Unknown(u8),
+}
+
+/// The cartridge type byte at 0147, split into its mapper and the hardware attached to it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mapper: Mapper,
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+    /// Accelerometer, only used by MBC7
+    pub sensor: bool,
+}
+
+impl CartridgeType {
+    pub fn from_byte(byte: u8) -> Self {
+        // (mapper, ram, battery, timer, rumble)
+        let (mapper, ram, battery, timer, rumble) = match byte {
+            0x00 => (Mapper::None, false, false, false, false),
+            0x01 => (Mapper::Mbc1, false, false, false, false),
+            0x02 => (Mapper::Mbc1, true, false, false, false),
+            0x03 => (Mapper::Mbc1, true, true, false, false),
+            0x05 => (Mapper::Mbc2, false, false, false, false),
+            0x06 => (Mapper::Mbc2, false, true, false, false),
+            0x08 => (Mapper::None, true, false, false, false),
+            0x09 => (Mapper::None, true, true, false, false),
+            0x0B => (Mapper::Mmm01, false, false, false, false),
+            0x0C => (Mapper::Mmm01, true, false, false, false),
+            0x0D => (Mapper::Mmm01, true, true, false, false),
+            0x0F => (Mapper::Mbc3, false, true, true, false),
+            0x10 => (Mapper::Mbc3, true, true, true, false),
+            0x11 => (Mapper::Mbc3, false, false, false, false),
+            0x12 => (Mapper::Mbc3, true, false, false, false),
+            0x13 => (Mapper::Mbc3, true, true, false, false),
+            0x19 => (Mapper::Mbc5, false, false, false, false),
+            0x1A => (Mapper::Mbc5, true, false, false, false),
+            0x1B => (Mapper::Mbc5, true, true, false, false),
+            0x1C => (Mapper::Mbc5, false, false, false, true),
+            0x1D => (Mapper::Mbc5, true, false, false, true),
+            0x1E => (Mapper::Mbc5, true, true, false, true),
+            0x20 => (Mapper::Mbc6, false, false, false, false),
+            0x22 => (Mapper::Mbc7, true, true, false, true),
+            0xFC => (Mapper::PocketCamera, true, true, false, false),
+            0xFD => (Mapper::Tama5, false, false, false, false),
+            0xFE => (Mapper::HuC3, true, true, true, false),
+            0xFF => (Mapper::HuC1, true, true, false, false),
+            e => (Mapper::Unknown(e), false, false, false, false),
+        };
+
+        Self {
+            code: byte,
+            mapper,
+            ram,
+            battery,
+            timer,
+            rumble,
+            sensor: mapper == Mapper::Mbc7,
+        }
+    }
+}
+
+impl CartridgeHeader {
+    /// Parses the header out of a full ROM image
+    ///
+    /// ### Panic Conditions
+    /// - Will panic if `rom` is too short to contain a header
+    /// - Will panic if the ROM or RAM size bytes are invalid
+    pub fn parse(rom: &[u8]) -> Self {
+        Self::try_parse(rom).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Parses the header out of a full ROM image
+    ///
+    /// ### Return Variants
+    /// - `Err(RomLoadError::Truncated)` if `rom` is too short to contain a header
+    /// - `Err(RomLoadError::InvalidRomSize)` or `Err(RomLoadError::InvalidRamSize)` if the size bytes are invalid
+    pub fn try_parse(rom: &[u8]) -> Result<Self, RomLoadError> {
+        if rom.len() < HEADER_END {
+            return Err(RomLoadError::Truncated { expected: HEADER_END, found: rom.len() });
+        }
+
+        // MMM01 collections keep the real header with the menu in the last 32KiB, bank 0 has the first game's
+        let full = rom;
+        let start = Self::header_offset(full);
+        let rom = &full[start..];
+
+        let cgb_flag = match rom[CGB_FLAG] {
+            0x80 => CgbFlag::Enhanced,
+            0xC0 => CgbFlag::Only,
+            _ => CgbFlag::Dmg,
+        };
+
+        // newer cartridges took the last 5 bytes of the title area for the manufacturer code and CGB flag
+        // there is no flag for the manufacturer code, but it's always 4 uppercase letters or digits
+        let manufacturer = &rom[MANUFACTURER_CODE..CGB_FLAG];
+        let manufacturer_code = if cgb_flag != CgbFlag::Dmg
+            && manufacturer.iter().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        {
+            Some(ascii(manufacturer))
+        } else {
+            None
+        };
+
+        let title_end = if manufacturer_code.is_some() {
+            MANUFACTURER_CODE
+        } else if cgb_flag != CgbFlag::Dmg {
+            CGB_FLAG
+        } else {
+            NEW_LICENSEE
+        };
+
+        let header_checksum = rom[HEADER_CHECKSUM];
+        let global_checksum = u16::from_be_bytes([rom[GLOBAL_CHECKSUM], rom[GLOBAL_CHECKSUM + 1]]);
+
+        Ok(Self {
+            title: ascii(&rom[TITLE..title_end]),
+            manufacturer_code,
+            cgb_flag,
+            new_licensee: ascii(&rom[NEW_LICENSEE..SGB_FLAG]),
+            old_licensee: rom[OLD_LICENSEE],
+            sgb: rom[SGB_FLAG] == 0x03,
+            cartridge_type: CartridgeType::from_byte(rom[MBC_ADDR]),
+            rom_size: RomSize::try_from(rom[ROM_SIZE])?,
+            ram_size: RamSize::try_from(rom[RAM_SIZE])?,
+            destination: if rom[DESTINATION] == 0x00 {
+                Destination::Japan
+            } else {
+                Destination::Overseas
+            },
+            version: rom[VERSION],
+            header_checksum,
+            header_checksum_valid: Self::compute_header_checksum(rom) == header_checksum,
+            global_checksum,
+            global_checksum_valid: global_sum(full, start + GLOBAL_CHECKSUM) == global_checksum,
+        })
+    }
+
+    /// Checks that `rom` is exactly as big as the header says it is
+    ///
+    /// ### Return Variants
+    /// - `Err(RomLoadError::Truncated)` if the image is smaller than the declared ROM size
+    /// - `Err(RomLoadError::Oversized)` if the image is bigger than the declared ROM size
+    pub fn check_image(&self, rom: &[u8]) -> Result<(), RomLoadError> {
+        let expected = convert_rom_size(&self.rom_size) * 0x4000;
+
+        if rom.len() < expected {
+            Err(RomLoadError::Truncated { expected, found: rom.len() })
+        } else if rom.len() > expected {
+            Err(RomLoadError::Oversized { max: expected, found: rom.len() })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Computes the header checksum the same way the boot ROM does
+    pub fn compute_header_checksum(rom: &[u8]) -> u8 {
+        rom[TITLE..HEADER_CHECKSUM]
+            .iter()
+            .fold(0u8, |acc, byte| acc.wrapping_sub(*byte).wrapping_sub(1))
+    }
+
+    /// Computes the sum of every byte in the ROM except the global checksum itself
+    pub fn compute_global_checksum(rom: &[u8]) -> u16 {
+        global_sum(rom, GLOBAL_CHECKSUM)
+    }
+
+    /// Finds where the header to use starts, which is only somewhere other than 0 for MMM01 collections
+    ///
+    /// Their menu is in the last 32KiB with its own header, and is recognized by having an MMM01 cartridge type
+    /// and a valid header checksum there
+    fn header_offset(rom: &[u8]) -> usize {
+        let is_mmm01 = |header: &[u8]| {
+            CartridgeType::from_byte(header[MBC_ADDR]).mapper == Mapper::Mmm01
+                && Self::compute_header_checksum(header) == header[HEADER_CHECKSUM]
+        };
+
+        if rom.len() > 0x8000 && !is_mmm01(rom) && is_mmm01(&rom[rom.len() - 0x8000..]) {
+            rom.len() - 0x8000
+        } else {
+            0
+        }
+    }
+
+    /// Returns the licensee code, picking between the old and new codes the same way the hardware does
+    pub fn licensee(&self) -> String {
+        if self.old_licensee == USE_NEW_LICENSEE {
+            self.new_licensee.clone()
+        } else {
+            format!("{:02X}", self.old_licensee)
+        }
+    }
+
+    /// Picks the MBC needed to run this cartridge
+    ///
+    /// ### Panic Conditions
+    /// - Will panic if the mapper isn't supported
+    pub fn mbc_selector(&self) -> MbcSelector {
+        self.try_mbc_selector().unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Picks the MBC needed to run this cartridge
+    ///
+    /// ### Return Variants
+    /// - `Err(RomLoadError::UnsupportedMapper)` if the mapper isn't emulated
+    pub fn try_mbc_selector(&self) -> Result<MbcSelector, RomLoadError> {
+        let cart = self.cartridge_type;
+        let rom_size = self.rom_size;
+        // some headers list a ram size even though the cartridge type says there is none
+        let ram_size = if cart.ram { self.ram_size } else { RamSize::Zero };
+
+        let selector = match cart.mapper {
+            Mapper::None => MbcSelector::NoMbc(ram_size, cart.battery),
+            Mapper::Mbc1 => MbcSelector::Mbc1(rom_size, ram_size, cart.battery),
+            Mapper::Mbc2 => MbcSelector::Mbc2(rom_size, cart.battery),
+            Mapper::Mbc3 => {
+                MbcSelector::Mbc3(rom_size, ram_size, Mbc3Features { timer: cart.timer, battery: cart.battery })
+            }
+            Mapper::Mbc5 => {
+                MbcSelector::Mbc5(rom_size, ram_size, Mbc5Features { rumble: cart.rumble, battery: cart.battery })
+            }
+            Mapper::Mmm01 => MbcSelector::Mmm01(rom_size, ram_size, cart.battery),
+            Mapper::Mbc7 => MbcSelector::Mbc7(rom_size),
+            Mapper::PocketCamera => MbcSelector::PocketCamera(rom_size),
+            Mapper::HuC1 => MbcSelector::HuC1(rom_size, ram_size),
+            Mapper::HuC3 => MbcSelector::HuC3(rom_size, ram_size),
+            _ => return Err(RomLoadError::UnsupportedMapper(cart.code)),
+        };
+
+        Ok(selector)
+    }
+}
+
+/// Sums every byte in the ROM except the 2 byte checksum at `at`
+fn global_sum(rom: &[u8], at: usize) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|(i, _)| *i != at && *i != at + 1)
+        .fold(0u16, |acc, (_, byte)| acc.wrapping_add(*byte as u16))
+}
+
+/// Converts a fixed size header string, ending at the first null byte
+fn ascii(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| if c.is_ascii_graphic() || *c == b' ' { *c as char } else { '?' })
+        .collect::<String>()
+        .trim_end()
+        .to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::mbc::{try_init_mbc, MbcSelector, RamSize, RomLoadError, RomSize};
+
+    use super::{CartridgeHeader, CgbFlag, Destination, Mapper, GLOBAL_CHECKSUM, HEADER_CHECKSUM};
+
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0134..0x013F].copy_from_slice(b"POKEMON_SLV");
+        rom[0x013F..0x0143].copy_from_slice(b"AAXE");
+        rom[0x0143] = 0x80;
+        rom[0x0144..0x0146].copy_from_slice(b"01");
+        rom[0x0146] = 0x03;
+        rom[0x0147] = 0x10;
+        rom[0x0149] = 0x03;
+        rom[0x014A] = 0x01;
+        rom[0x014B] = 0x33;
+
+        rom[HEADER_CHECKSUM] = CartridgeHeader::compute_header_checksum(&rom);
+        let global = CartridgeHeader::compute_global_checksum(&rom).to_be_bytes();
+        rom[GLOBAL_CHECKSUM..GLOBAL_CHECKSUM + 2].copy_from_slice(&global);
+
+        rom
+    }
+
+    #[test]
+    fn parse() {
+        let header = CartridgeHeader::parse(&rom());
+
+        assert_eq!(header.title, "POKEMON_SLV");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("AAXE"));
+        assert_eq!(header.cgb_flag, CgbFlag::Enhanced);
+        assert_eq!(header.licensee(), "01");
+        assert!(header.sgb);
+        assert_eq!(header.cartridge_type.mapper, Mapper::Mbc3);
+        assert!(header.cartridge_type.timer && header.cartridge_type.battery);
+        assert_eq!(header.destination, Destination::Overseas);
+        assert!(header.header_checksum_valid);
+        assert!(header.global_checksum_valid);
+    }
+
+    #[test]
+    fn bad_checksums() {
+        let mut rom = rom();
+        rom[0x0134] = b'Q';
+        rom[0x4000] = 1;
+
+        let header = CartridgeHeader::parse(&rom);
+        assert!(!header.header_checksum_valid);
+        assert!(!header.global_checksum_valid);
+    }
+
+    #[test]
+    fn old_title() {
+        let mut rom = rom();
+        rom[0x0134..0x0144].copy_from_slice(b"TETRIS\0\0\0\0\0\0\0\0\0\0");
+
+        let header = CartridgeHeader::parse(&rom);
+        assert_eq!(header.title, "TETRIS");
+        assert_eq!(header.manufacturer_code, None);
+        assert_eq!(header.cgb_flag, CgbFlag::Dmg);
+    }
+
+    #[test]
+    fn load_errors() {
+        assert_eq!(
+            CartridgeHeader::try_parse(&[0; 0x100]).err(),
+            Some(RomLoadError::Truncated { expected: 0x150, found: 0x100 })
+        );
+
+        let mut rom = rom();
+        rom[0x0148] = 0x52;
+        assert_eq!(CartridgeHeader::try_parse(&rom).err(), Some(RomLoadError::InvalidRomSize(0x52)));
+
+        let mut rom = self::rom();
+        rom[0x0147] = 0xFD;
+        let header = CartridgeHeader::try_parse(&rom).unwrap();
+        assert_eq!(header.try_mbc_selector(), Err(RomLoadError::UnsupportedMapper(0xFD)));
+
+        // header says 64KiB
+        let mut rom = self::rom();
+        rom[0x0148] = 0x01;
+        let header = CartridgeHeader::try_parse(&rom).unwrap();
+        assert_eq!(
+            header.check_image(&rom),
+            Err(RomLoadError::Truncated { expected: 0x10000, found: 0x8000 })
+        );
+
+        let selector = MbcSelector::Mbc1(RomSize::Eight, RamSize::Zero, false);
+        assert_eq!(try_init_mbc(selector).err(), Some(RomLoadError::HeaderMismatch(selector)));
+    }
+
+    #[test]
+    fn mmm01_menu_header() {
+        let mut rom = vec![0; 0x4000 * 4];
+        // the first game is a plain MBC1 cartridge
+        rom[0x0147] = 0x01;
+
+        let menu = rom.len() - 0x8000;
+        rom[menu + 0x0147] = 0x0D;
+        rom[menu + 0x0148] = 0x01;
+        rom[menu + 0x0149] = 0x02;
+        rom[menu + HEADER_CHECKSUM] = CartridgeHeader::compute_header_checksum(&rom[menu..]);
+
+        let header = CartridgeHeader::parse(&rom);
+        assert_eq!(header.cartridge_type.mapper, Mapper::Mmm01);
+        assert!(header.header_checksum_valid);
+        assert_eq!(
+            header.mbc_selector(),
+            MbcSelector::Mmm01(RomSize::One, RamSize::Two, true)
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use super::{dump_banks, load_banks, load_rom_bank, restore_banks, Mbc, MbcAddr, RomLoadError, SaveError};
+
+/// Value written to 0000-1FFF to map the IR port over the RAM area
+pub(crate) const IR_MODE: u8 = 0x0E;
+
+#[derive(Clone)]
+pub struct HuC1 {
+    /// Cartridge ROM, up to 64 banks, each 16384 bytes
+    pub rom: Box<[Box<[Option<u8>; 0x4000]>]>,
+    /// Cartridge RAM, up to 4 banks, each 8192 bytes
+    pub ram: Box<[Box<[Option<u8>; 0x2000]>]>,
+    pub rom_bank: u8,
+    pub ram_bank: u8,
+    /// Whether A000-BFFF is mapped to the IR port instead of RAM
+    pub ir_mode: bool,
+    /// Whether the IR LED is lit
+    pub ir_led: bool,
+    /// Whether the IR receiver is seeing light, set by the host
+    pub ir_input: bool,
+}
+
+impl Mbc for HuC1 {
+    fn load(&self, addr: u16) -> Option<u8> {
+        let addr = self.translate(addr);
+
+        match addr {
+            MbcAddr::Rom0(a) => self.rom[0][a as usize],
+            MbcAddr::RomX(a) => self.rom[self.rom_bank as usize][a as usize],
+            MbcAddr::Ram(_) if self.ir_mode => Some(0xC0 | self.ir_input as u8),
+            MbcAddr::Ram(a) => self
+                .ram
+                .get(self.ram_bank as usize)
+                .map_or(Some(0xFF), |bank| bank[a as usize]),
+        }
+    }
+
+    fn set(&mut self, addr: u16, value: u8) {
+        match self.translate(addr) {
+            MbcAddr::Rom0(_) | MbcAddr::RomX(_) => match addr {
+                0x0000..=0x1FFF => {
+                    self.ir_mode = value & 0x0F == IR_MODE;
+                }
+                0x2000..=0x3FFF => {
+                    let bank = value & 0x3F;
+                    let bank = if bank == 0 { 1 } else { bank };
+
+                    self.rom_bank = bank & (self.rom.len() - 1) as u8;
+                }
+                0x4000..=0x5FFF => {
+                    self.ram_bank = value & 0x03;
+                }
+                0x6000..=0x7FFF => {}
+                _ => unreachable!(),
+            },
+            MbcAddr::Ram(_) if self.ir_mode => self.ir_led = value & 0x01 == 0x01,
+            MbcAddr::Ram(a) => {
+                if let Some(bank) = self.ram.get_mut(self.ram_bank as usize) {
+                    bank[a as usize] = Some(value);
+                }
+            }
+        }
+    }
+
+    fn try_load_rom(&mut self, data: &[u8]) -> Result<(), RomLoadError> {
+        load_banks(&mut self.rom, data)
+    }
+
+    fn translate(&self, addr: u16) -> MbcAddr {
+        match addr {
+            0x0000..=0x3FFF => MbcAddr::Rom0(addr),
+            0x4000..=0x7FFF => MbcAddr::RomX(addr - 0x4000),
+            0xA000..=0xBFFF => MbcAddr::Ram(addr - 0xA000),
+            _ => panic!("Invalid memory translation: ${addr:#06x}"),
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+
+    fn load_rom_bank(&self, bank: usize, addr: u16) -> Option<u8> {
+        load_rom_bank(&self.rom, bank, addr)
+    }
+
+    fn has_battery(&self) -> bool {
+        true
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        dump_banks(&self.ram)
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) -> Result<(), SaveError> {
+        restore_banks(&mut self.ram, data)
+    }
+
+    fn ir_led(&self) -> bool {
+        self.ir_led
+    }
+
+    fn set_ir_input(&mut self, light: bool) {
+        self.ir_input = light;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::mbc::{init_mbc, MbcSelector, RamSize, RomSize};
+
+    #[test]
+    fn ir_mode() {
+        let mut mbc = init_mbc(MbcSelector::HuC1(RomSize::Zero, RamSize::Two));
+        mbc.set(0xA000, 0x45);
+
+        mbc.set(0x0000, 0x0E);
+        assert_eq!(mbc.load(0xA000), Some(0xC0));
+        mbc.set_ir_input(true);
+        assert_eq!(mbc.load(0xA000), Some(0xC1));
+
+        // writes go to the LED instead of RAM
+        mbc.set(0xA000, 0x01);
+        assert!(mbc.ir_led());
+
+        mbc.set(0x0000, 0x00);
+        assert_eq!(mbc.load(0xA000), Some(0x45));
+    }
+}
@@ -0,0 +1,341 @@
+use super::{
+    dump_banks, huc1::IR_MODE, load_banks, load_rom_bank, restore_banks, unix_time, Mbc, MbcAddr, RomLoadError,
+    SaveError,
+};
+
+/// RAM is readable but not writable
+const MODE_RAM_READ: u8 = 0x00;
+/// RAM is readable and writable
+const MODE_RAM: u8 = 0x0A;
+/// Writes to the RAM area are clock commands
+const MODE_COMMAND: u8 = 0x0B;
+/// Reads from the RAM area return the result of the last clock command
+const MODE_RESPONSE: u8 = 0x0C;
+/// Reads from the RAM area return whether the clock is ready for another command
+const MODE_SEMAPHORE: u8 = 0x0D;
+
+/// Read the nibble at the clock address into the response, then increment the address
+const CMD_READ: u8 = 0x1;
+/// Write the argument to the clock address, then increment the address
+const CMD_WRITE: u8 = 0x3;
+/// Set the lower nibble of the clock address
+const CMD_ADDR_LOW: u8 = 0x4;
+/// Set the upper nibble of the clock address
+const CMD_ADDR_HIGH: u8 = 0x5;
+/// Extended command, picked by the argument
+const CMD_EXTENDED: u8 = 0x6;
+
+/// Copy the current time into clock memory 00-05
+const EXT_LATCH: u8 = 0x0;
+/// Set the current time from clock memory 00-05
+const EXT_SET: u8 = 0x1;
+/// Respond with 1 so the game knows the clock is alive
+const EXT_STATUS: u8 = 0x2;
+
+/// Size of the clock footer appended to save files
+pub const CLOCK_FOOTER_LEN: usize = 12;
+
+#[derive(Clone)]
+pub struct HuC3 {
+    /// Cartridge ROM, up to 128 banks, each 16384 bytes
+    pub rom: Box<[Box<[Option<u8>; 0x4000]>]>,
+    /// Cartridge RAM, up to 4 banks, each 8192 bytes
+    pub ram: Box<[Box<[Option<u8>; 0x2000]>]>,
+    pub rom_bank: u8,
+    pub ram_bank: u8,
+    /// Picks what the RAM area is mapped to, written to 0000-1FFF
+    pub mode: u8,
+    pub clock: HuC3Clock,
+    /// Whether the IR LED is lit
+    pub ir_led: bool,
+    /// Whether the IR receiver is seeing light, set by the host
+    pub ir_input: bool,
+}
+
+/// The HuC3 clock, which counts minutes and days and is driven through a nibble wide command interface
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HuC3Clock {
+    /// Minutes into the current day
+    pub minutes: u16,
+    /// 12 bit day counter
+    pub days: u16,
+    /// Nibble memory the commands read and write, the time lives in 00-05
+    pub memory: [u8; 0x100],
+    /// Address into `memory` used by the read and write commands
+    pub address: u8,
+    /// Result of the last command
+    pub response: u8,
+    /// Unix timestamp the counters were last brought up to date at
+    pub last_update: u64,
+}
+
+impl Default for HuC3Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HuC3Clock {
+    pub fn new() -> Self {
+        Self {
+            minutes: 0,
+            days: 0,
+            memory: [0; 0x100],
+            address: 0,
+            response: 0,
+            last_update: unix_time(),
+        }
+    }
+
+    /// Advances the counters by the whole minutes passed since the last update
+    pub fn update(&mut self) {
+        let now = unix_time();
+        let minutes = now.saturating_sub(self.last_update) / 60;
+
+        // keep the leftover seconds so they count towards the next minute
+        self.last_update += minutes * 60;
+        self.advance(minutes);
+    }
+
+    /// Advances the counters by `minutes` minutes
+    pub fn advance(&mut self, minutes: u64) {
+        let minutes = self.minutes as u64 + minutes;
+        self.minutes = (minutes % 1440) as u16;
+        self.days = ((self.days as u64 + minutes / 1440) % 0x1000) as u16;
+    }
+
+    /// Runs a command byte written in command mode
+    pub fn command(&mut self, value: u8) {
+        let cmd = (value >> 4) & 0x07;
+        let arg = value & 0x0F;
+
+        match cmd {
+            CMD_READ => {
+                self.respond(cmd, self.memory[self.address as usize]);
+                self.address = self.address.wrapping_add(1);
+            }
+            CMD_WRITE => {
+                self.memory[self.address as usize] = arg;
+                self.address = self.address.wrapping_add(1);
+            }
+            CMD_ADDR_LOW => self.address = (self.address & 0xF0) | arg,
+            CMD_ADDR_HIGH => self.address = (self.address & 0x0F) | (arg << 4),
+            CMD_EXTENDED => match arg {
+                EXT_LATCH => {
+                    self.update();
+                    self.store(0x00, self.minutes);
+                    self.store(0x03, self.days);
+                }
+                EXT_SET => {
+                    self.minutes = self.fetch(0x00) % 1440;
+                    self.days = self.fetch(0x03);
+                    self.last_update = unix_time();
+                }
+                EXT_STATUS => self.respond(cmd, 0x01),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn respond(&mut self, cmd: u8, value: u8) {
+        self.response = 0x80 | (cmd << 4) | (value & 0x0F);
+    }
+
+    /// Stores a 12 bit value as 3 nibbles, lowest first
+    fn store(&mut self, start: usize, value: u16) {
+        for i in 0..3 {
+            self.memory[start + i] = (value >> (i * 4)) as u8 & 0x0F;
+        }
+    }
+
+    /// Fetches a 12 bit value stored by `HuC3Clock::store()`
+    fn fetch(&self, start: usize) -> u16 {
+        (0..3).fold(0, |acc, i| {
+            acc | ((self.memory[start + i] as u16 & 0x0F) << (i * 4))
+        })
+    }
+
+    /// Serializes the clock as the minutes and days as little endian 16 bit values,
+    /// followed by the Unix timestamp of the last update as a little endian 64 bit value
+    pub fn to_footer(&self) -> [u8; CLOCK_FOOTER_LEN] {
+        let mut out = [0; CLOCK_FOOTER_LEN];
+        out[0..2].copy_from_slice(&self.minutes.to_le_bytes());
+        out[2..4].copy_from_slice(&self.days.to_le_bytes());
+        out[4..12].copy_from_slice(&self.last_update.to_le_bytes());
+        out
+    }
+
+    /// Restores the clock from a footer produced by `HuC3Clock::to_footer()`, then catches it up to the current time
+    pub fn from_footer(&mut self, footer: &[u8]) -> Result<(), SaveError> {
+        if footer.len() != CLOCK_FOOTER_LEN {
+            return Err(SaveError::InvalidSize {
+                expected: CLOCK_FOOTER_LEN,
+                found: footer.len(),
+            });
+        }
+
+        self.minutes = u16::from_le_bytes([footer[0], footer[1]]) % 1440;
+        self.days = u16::from_le_bytes([footer[2], footer[3]]) & 0x0FFF;
+        self.last_update = u64::from_le_bytes(footer[4..12].try_into().unwrap());
+        self.update();
+
+        Ok(())
+    }
+}
+
+impl Mbc for HuC3 {
+    fn load(&self, addr: u16) -> Option<u8> {
+        let addr = self.translate(addr);
+
+        match addr {
+            MbcAddr::Rom0(a) => self.rom[0][a as usize],
+            MbcAddr::RomX(a) => self.rom[self.rom_bank as usize][a as usize],
+            MbcAddr::Ram(a) => match self.mode {
+                MODE_RAM_READ | MODE_RAM => self
+                    .ram
+                    .get(self.ram_bank as usize)
+                    .map_or(Some(0xFF), |bank| bank[a as usize]),
+                MODE_RESPONSE => Some(self.clock.response),
+                // commands finish instantly, so the clock is always ready
+                MODE_SEMAPHORE => Some(0x01),
+                IR_MODE => Some(0xC0 | self.ir_input as u8),
+                _ => Some(0xFF),
+            },
+        }
+    }
+
+    fn set(&mut self, addr: u16, value: u8) {
+        match self.translate(addr) {
+            MbcAddr::Rom0(_) | MbcAddr::RomX(_) => match addr {
+                0x0000..=0x1FFF => {
+                    self.mode = value & 0x0F;
+                }
+                0x2000..=0x3FFF => {
+                    let bank = value & 0x7F;
+                    let bank = if bank == 0 { 1 } else { bank };
+
+                    self.rom_bank = bank & (self.rom.len() - 1) as u8;
+                }
+                0x4000..=0x5FFF => {
+                    self.ram_bank = value & 0x03;
+                }
+                0x6000..=0x7FFF => {}
+                _ => unreachable!(),
+            },
+            MbcAddr::Ram(a) => match self.mode {
+                MODE_RAM => {
+                    if let Some(bank) = self.ram.get_mut(self.ram_bank as usize) {
+                        bank[a as usize] = Some(value);
+                    }
+                }
+                MODE_COMMAND => self.clock.command(value),
+                IR_MODE => self.ir_led = value & 0x01 == 0x01,
+                _ => {}
+            },
+        }
+    }
+
+    fn try_load_rom(&mut self, data: &[u8]) -> Result<(), RomLoadError> {
+        load_banks(&mut self.rom, data)
+    }
+
+    fn translate(&self, addr: u16) -> MbcAddr {
+        match addr {
+            0x0000..=0x3FFF => MbcAddr::Rom0(addr),
+            0x4000..=0x7FFF => MbcAddr::RomX(addr - 0x4000),
+            0xA000..=0xBFFF => MbcAddr::Ram(addr - 0xA000),
+            _ => panic!("Invalid memory translation: ${addr:#06x}"),
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+
+    fn load_rom_bank(&self, bank: usize, addr: u16) -> Option<u8> {
+        load_rom_bank(&self.rom, bank, addr)
+    }
+
+    fn has_battery(&self) -> bool {
+        true
+    }
+
+    /// Dumps RAM followed by the 12 byte clock footer
+    fn dump_ram(&self) -> Vec<u8> {
+        let mut clock = self.clock;
+        clock.update();
+
+        let mut out = dump_banks(&self.ram);
+        out.extend_from_slice(&clock.to_footer());
+        out
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) -> Result<(), SaveError> {
+        let ram_len = self.ram.len() * 0x2000;
+        if data.len() <= ram_len {
+            return restore_banks(&mut self.ram, data);
+        }
+
+        let (ram, footer) = data.split_at(ram_len);
+        self.clock.from_footer(footer)?;
+        restore_banks(&mut self.ram, ram)
+    }
+
+    fn ir_led(&self) -> bool {
+        self.ir_led
+    }
+
+    fn set_ir_input(&mut self, light: bool) {
+        self.ir_input = light;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::mbc::{init_mbc, MbcSelector, RamSize, RomSize};
+
+    use super::HuC3Clock;
+
+    #[test]
+    fn clock_commands() {
+        let mut mbc = init_mbc(MbcSelector::HuC3(RomSize::Zero, RamSize::Two));
+        mbc.set(0x0000, 0x0B);
+
+        // set the time to 0x123 minutes, 0x045 days
+        mbc.set(0xA000, 0x40);
+        mbc.set(0xA000, 0x50);
+        for nibble in [0x3, 0x2, 0x1, 0x5, 0x4, 0x0] {
+            mbc.set(0xA000, 0x30 | nibble);
+        }
+        mbc.set(0xA000, 0x61);
+
+        // clear the nibbles, then latch the time back into them
+        mbc.set(0xA000, 0x40);
+        for _ in 0..6 {
+            mbc.set(0xA000, 0x30);
+        }
+        mbc.set(0xA000, 0x60);
+
+        mbc.set(0xA000, 0x40);
+        let mut nibbles = Vec::new();
+        for _ in 0..6 {
+            mbc.set(0x0000, 0x0B);
+            mbc.set(0xA000, 0x10);
+            mbc.set(0x0000, 0x0C);
+            nibbles.push(mbc.load(0xA000).unwrap() & 0x0F);
+        }
+
+        assert_eq!(nibbles, [0x3, 0x2, 0x1, 0x5, 0x4, 0x0]);
+    }
+
+    #[test]
+    fn clock_rollover() {
+        let mut clock = HuC3Clock::new();
+        clock.minutes = 1439;
+        clock.days = 0xFFF;
+
+        clock.advance(1);
+        assert_eq!((clock.minutes, clock.days), (0, 0));
+    }
+}
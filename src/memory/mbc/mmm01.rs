@@ -0,0 +1,223 @@
+use super::{dump_banks, load_banks, load_rom_bank, restore_banks, Mbc, MbcAddr, RomLoadError, SaveError};
+
+#[derive(Clone)]
+pub struct Mmm01 {
+    /// Cartridge ROM, up to 512 banks, each 16384 bytes
+    pub rom: Box<[Box<[Option<u8>; 0x4000]>]>,
+    /// Cartridge RAM, up to 16 banks, each 8192 bytes
+    pub ram: Box<[Box<[Option<u8>; 0x2000]>]>,
+    /// Cleared at power on, while unlocked the menu in the last 32KiB of ROM is mapped and it can pick a game
+    ///
+    /// Once locked, only the MBC1 registers of the selected game can be changed until the next power cycle
+    pub locked: bool,
+    /// Bits 0-4 of the ROM bank number
+    pub rom_bank_low: u8,
+    /// Bits 5-6 of the ROM bank number, only writable while unlocked
+    pub rom_bank_mid: u8,
+    /// Bits 7-8 of the ROM bank number, only writable while unlocked
+    pub rom_bank_high: u8,
+    /// Bits 1-4 of `rom_bank_low` that are fixed by the menu once locked
+    pub rom_bank_mask: u8,
+    /// Bits 0-1 of the RAM bank number
+    pub ram_bank_low: u8,
+    /// Bits 2-3 of the RAM bank number, only writable while unlocked
+    pub ram_bank_high: u8,
+    /// Bits of `ram_bank_low` that are fixed by the menu once locked
+    pub ram_bank_mask: u8,
+    pub ram_enabled: bool,
+    /// MBC1 banking mode, when clear only RAM bank 0 of the game is used
+    pub mode: bool,
+    /// Stops the game from changing `mode`
+    pub mode_locked: bool,
+    pub battery: bool,
+}
+
+impl Mmm01 {
+    /// ROM bank mapped into 0000-3FFF
+    pub fn rom0_bank(&self) -> usize {
+        if !self.locked {
+            // the menu lives in the last 32KiB
+            return self.rom.len() - 2;
+        }
+
+        let fixed = self.rom_bank_mask << 1;
+        let bank = ((self.rom_bank_high as usize) << 7)
+            | ((self.rom_bank_mid as usize) << 5)
+            | (self.rom_bank_low & fixed) as usize;
+
+        bank & (self.rom.len() - 1)
+    }
+
+    /// ROM bank mapped into 4000-7FFF
+    pub fn romx_bank(&self) -> usize {
+        if !self.locked {
+            return self.rom.len() - 1;
+        }
+
+        // like MBC1, bank 0 of the game can't be mapped here
+        let fixed = self.rom_bank_mask << 1;
+        let low = if self.rom_bank_low & !fixed & 0x1F == 0 {
+            self.rom_bank_low | 1
+        } else {
+            self.rom_bank_low
+        };
+
+        let bank = ((self.rom_bank_high as usize) << 7)
+            | ((self.rom_bank_mid as usize) << 5)
+            | low as usize;
+
+        bank & (self.rom.len() - 1)
+    }
+
+    /// RAM bank mapped into A000-BFFF
+    pub fn ram_bank(&self) -> usize {
+        let low = if self.mode || !self.locked {
+            self.ram_bank_low
+        } else {
+            self.ram_bank_low & self.ram_bank_mask
+        };
+
+        (((self.ram_bank_high << 2) | low) as usize) & self.ram.len().saturating_sub(1)
+    }
+}
+
+impl Mbc for Mmm01 {
+    fn load(&self, addr: u16) -> Option<u8> {
+        let addr = self.translate(addr);
+
+        match addr {
+            MbcAddr::Rom0(a) => self.rom[self.rom0_bank()][a as usize],
+            MbcAddr::RomX(a) => self.rom[self.romx_bank()][a as usize],
+            MbcAddr::Ram(a) => {
+                if self.ram_enabled {
+                    self.ram
+                        .get(self.ram_bank())
+                        .map_or(Some(0xFF), |bank| bank[a as usize])
+                } else {
+                    Some(0xFF)
+                }
+            }
+        }
+    }
+
+    fn set(&mut self, addr: u16, value: u8) {
+        match self.translate(addr) {
+            MbcAddr::Rom0(_) | MbcAddr::RomX(_) => match addr {
+                0x0000..=0x1FFF => {
+                    self.ram_enabled = value & 0x0F == 0x0A;
+
+                    if !self.locked {
+                        self.ram_bank_mask = (value >> 4) & 0b11;
+                        self.locked = value & 0b0100_0000 > 0;
+                    }
+                }
+                0x2000..=0x3FFF => {
+                    if self.locked {
+                        let fixed = self.rom_bank_mask << 1;
+                        self.rom_bank_low = (self.rom_bank_low & fixed) | (value & 0x1F & !fixed);
+                    } else {
+                        self.rom_bank_low = value & 0x1F;
+                        self.rom_bank_mid = (value >> 5) & 0b11;
+                    }
+                }
+                0x4000..=0x5FFF => {
+                    if self.locked {
+                        let fixed = self.ram_bank_mask;
+                        self.ram_bank_low = (self.ram_bank_low & fixed) | (value & 0b11 & !fixed);
+                    } else {
+                        self.ram_bank_low = value & 0b11;
+                        self.ram_bank_high = (value >> 2) & 0b11;
+                        self.rom_bank_high = (value >> 4) & 0b11;
+                        self.mode_locked = value & 0b0100_0000 > 0;
+                    }
+                }
+                0x6000..=0x7FFF => {
+                    if !self.mode_locked {
+                        self.mode = value & 0x01 == 0x01;
+                    }
+
+                    if !self.locked {
+                        self.rom_bank_mask = (value >> 2) & 0x0F;
+                    }
+                }
+                _ => unreachable!(),
+            },
+            MbcAddr::Ram(a) => {
+                if self.ram_enabled {
+                    let bank = self.ram_bank();
+                    if let Some(bank) = self.ram.get_mut(bank) {
+                        bank[a as usize] = Some(value);
+                    }
+                }
+            }
+        }
+    }
+
+    fn try_load_rom(&mut self, data: &[u8]) -> Result<(), RomLoadError> {
+        load_banks(&mut self.rom, data)
+    }
+
+    fn translate(&self, addr: u16) -> MbcAddr {
+        match addr {
+            0x0000..=0x3FFF => MbcAddr::Rom0(addr),
+            0x4000..=0x7FFF => MbcAddr::RomX(addr - 0x4000),
+            0xA000..=0xBFFF => MbcAddr::Ram(addr - 0xA000),
+            _ => panic!("Invalid memory translation: ${addr:#06x}"),
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.romx_bank()
+    }
+
+    fn load_rom_bank(&self, bank: usize, addr: u16) -> Option<u8> {
+        load_rom_bank(&self.rom, bank, addr)
+    }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        dump_banks(&self.ram)
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) -> Result<(), SaveError> {
+        restore_banks(&mut self.ram, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::mbc::{init_mbc, MbcSelector, RamSize, RomSize};
+
+    #[test]
+    fn menu_then_game() {
+        let mut mbc = init_mbc(MbcSelector::Mmm01(RomSize::Four, RamSize::Zero, false));
+        let mut rom = vec![0; 0x4000 * 32];
+        rom[0x4000 * 30] = 0x30;
+        rom[0x4000 * 31] = 0x31;
+        rom[0x4000 * 8] = 0x08;
+        rom[0x4000 * 9] = 0x09;
+        rom[0x4000 * 10] = 0x0A;
+        mbc.load_rom(&rom);
+
+        // the menu is mapped at power on
+        assert_eq!(mbc.load(0x0000), Some(0x30));
+        assert_eq!(mbc.load(0x4000), Some(0x31));
+
+        // game starts at bank 8, and is 4 banks big so bits 2-4 are fixed
+        mbc.set(0x2000, 0x08);
+        mbc.set(0x6000, 0b0011_1000);
+        mbc.set(0x0000, 0x40);
+
+        assert_eq!(mbc.load(0x0000), Some(0x08));
+        assert_eq!(mbc.load(0x4000), Some(0x09));
+
+        // the game can only reach its own banks
+        mbc.set(0x2000, 0x02);
+        assert_eq!(mbc.load(0x4000), Some(0x0A));
+        mbc.set(0x2000, 0x10);
+        assert_eq!(mbc.load(0x4000), Some(0x09));
+    }
+}
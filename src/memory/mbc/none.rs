@@ -1,9 +1,11 @@
-use super::{Mbc, MbcAddr};
+use super::{dump_banks, restore_banks, Mbc, MbcAddr, RomLoadError, SaveError};
 
 #[derive(Clone)]
 pub struct NoMbc {
     pub(crate) rom: Box<[Option<u8>; 0x8000]>,
-    pub(crate) ram: Box<[Option<u8>; 0x2000]>,
+    /// Either no RAM or a single 8KiB bank
+    pub(crate) ram: Box<[Box<[Option<u8>; 0x2000]>]>,
+    pub(crate) battery: bool,
 }
 
 impl Mbc for NoMbc {
@@ -13,16 +15,14 @@ impl Mbc for NoMbc {
         match addr {
             MbcAddr::Rom0(a) => self.rom[a as usize],
             MbcAddr::RomX(_) => unreachable!(),
-            MbcAddr::Ram(a) => self.ram[a as usize],
+            // open bus if the cartridge has no RAM
+            MbcAddr::Ram(a) => self.ram.first().map_or(Some(0xFF), |bank| bank[a as usize]),
         }
     }
 
     fn set(&mut self, addr: u16, value: u8) {
-        let addr = self.translate(addr);
-
-        match addr {
-            MbcAddr::Ram(a) => self.ram[a as usize] = Some(value),
-            _ => {},
+        if let (MbcAddr::Ram(a), Some(bank)) = (self.translate(addr), self.ram.first_mut()) {
+            bank[a as usize] = Some(value);
         }
     }
 
@@ -34,19 +34,56 @@ impl Mbc for NoMbc {
         }
     }
 
-    fn load_rom(&mut self, data: &[u8]) {
-        match self.translate((data.len() - 1) as u16) {
-            MbcAddr::Rom0(_) => {
-                for addr in 0..data.len() {
-                    let MbcAddr::Rom0(addr) = self.translate(addr as u16) else {
-                        panic!("That's not right");
-                    };
-                    self.rom[addr as usize] = Some(data[addr as usize]);
-                }
-            }
-            MbcAddr::RomX(_) => unreachable!(),
-            // the translate method should have panicked if addr was outside of the entire MBC
-            MbcAddr::Ram(_) => panic!("He ROM too big for he got damn MBC"),
-        };
+    fn rom_bank(&self) -> usize {
+        1
+    }
+
+    fn load_rom_bank(&self, bank: usize, addr: u16) -> Option<u8> {
+        if bank > 1 {
+            return None;
+        }
+
+        self.rom[bank * 0x4000 + (addr & 0x3FFF) as usize]
+    }
+
+    fn try_load_rom(&mut self, data: &[u8]) -> Result<(), RomLoadError> {
+        if data.len() > self.rom.len() {
+            return Err(RomLoadError::Oversized { max: self.rom.len(), found: data.len() });
+        }
+
+        for (cell, byte) in self.rom.iter_mut().zip(data) {
+            *cell = Some(*byte);
+        }
+
+        Ok(())
+    }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        dump_banks(&self.ram)
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) -> Result<(), SaveError> {
+        restore_banks(&mut self.ram, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::mbc::{init_mbc, MbcSelector, RamSize};
+
+    #[test]
+    fn header_ram_size() {
+        let mut mbc = init_mbc(MbcSelector::NoMbc(RamSize::Zero, false));
+        mbc.set(0xA000, 0x45);
+        assert_eq!(mbc.load(0xA000), Some(0xFF));
+
+        let mut mbc = init_mbc(MbcSelector::NoMbc(RamSize::Two, true));
+        mbc.set(0xA000, 0x45);
+        assert_eq!(mbc.load(0xA000), Some(0x45));
+        assert_eq!(mbc.dump_ram().len(), 0x2000);
     }
 }
@@ -1,4 +1,9 @@
-use super::{Mbc, MbcAddr};
+use super::{dump_banks, load_banks, load_rom_bank, restore_banks, Mbc, MbcAddr, RomLoadError, SaveError};
+
+use super::header::{LOGO, NINTENDO_LOGO};
+
+/// Each game in a multicart is 256KiB, 16 banks
+const MULTICART_GAME_BANKS: usize = 16;
 
 #[derive(Clone)]
 pub struct Mbc1 {
@@ -6,10 +11,73 @@ pub struct Mbc1 {
     pub rom: Box<[Box<[Option<u8>; 0x4000]>]>,
     /// Cartridge RAM, up to 4 banks, each 8192 bytes
     pub ram: Box<[Box<[Option<u8>; 0x2000]>]>,
-    pub rom_bank: u8,
-    pub ram_bank: u8,
+    /// 5 bit lower ROM bank register, written as 0 it selects bank 1
+    pub bank1: u8,
+    /// 2 bit register holding either the upper ROM bank bits or the RAM bank
+    pub bank2: u8,
     pub ram_enabled: bool,
-    pub ram_banking: bool,
+    /// Banking mode, when set `bank2` also applies to 0000-3FFF and the RAM area
+    pub mode: bool,
+    /// Whether this is an MBC1M multicart, which wires `bank2` to bits 4-5 of the bank number instead of 5-6
+    pub multicart: bool,
+    pub battery: bool,
+}
+
+impl Mbc1 {
+    /// Number of bits `bank2` is shifted by to form the ROM bank number
+    fn bank2_shift(&self) -> u8 {
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+
+    /// ROM bank mapped into 0000-3FFF
+    pub fn rom0_bank(&self) -> usize {
+        let bank = if self.mode { (self.bank2 << self.bank2_shift()) as usize } else { 0 };
+        bank & (self.rom.len() - 1)
+    }
+
+    /// ROM bank mapped into 4000-7FFF
+    pub fn romx_bank(&self) -> usize {
+        // multicarts don't wire up bit 4 of `bank1`, it's still used for the zero check though
+        let bank1 = if self.multicart { self.bank1 & 0x0F } else { self.bank1 };
+        let bank = (self.bank2 << self.bank2_shift()) as usize | bank1 as usize;
+        bank & (self.rom.len() - 1)
+    }
+
+    /// RAM bank mapped into A000-BFFF
+    pub fn ram_bank(&self) -> usize {
+        if self.mode {
+            self.bank2 as usize
+        } else {
+            0
+        }
+    }
+
+    /// Looks for the games of an MBC1M multicart, each with its own header at a 256KiB boundary
+    fn detect_multicart(&self) -> bool {
+        // every known multicart is 1MiB, a regular 1MiB game would just have code at these offsets
+        if self.rom.len() != 64 {
+            return false;
+        }
+
+        let logos = self
+            .rom
+            .iter()
+            .step_by(MULTICART_GAME_BANKS)
+            .filter(|bank| {
+                bank[LOGO..LOGO + NINTENDO_LOGO.len()]
+                    .iter()
+                    .zip(NINTENDO_LOGO)
+                    .all(|(cell, byte)| *cell == Some(byte))
+            })
+            .count();
+
+        // the menu and at least one game
+        logos >= 2
+    }
 }
 
 impl Mbc for Mbc1 {
@@ -17,11 +85,13 @@ impl Mbc for Mbc1 {
         let addr = self.translate(addr);
 
         match addr {
-            MbcAddr::Rom0(a) => self.rom[0][a as usize],
-            MbcAddr::RomX(a) => self.rom[self.rom_bank as usize][a as usize],
+            MbcAddr::Rom0(a) => self.rom[self.rom0_bank()][a as usize],
+            MbcAddr::RomX(a) => self.rom[self.romx_bank()][a as usize],
             MbcAddr::Ram(a) => {
                 if self.ram_enabled {
-                    self.ram[self.ram_bank as usize][a as usize]
+                    self.ram
+                        .get(self.ram_bank() & self.ram.len().saturating_sub(1))
+                        .map_or(Some(0xFF), |bank| bank[a as usize])
                 } else {
                     Some(0xFF)
                 }
@@ -30,90 +100,39 @@ impl Mbc for Mbc1 {
     }
 
     fn set(&mut self, addr: u16, value: u8) {
-        let addr = self.translate(addr);
-
-        match addr {
-            MbcAddr::Rom0(a) | MbcAddr::RomX(a) => match a {
+        match self.translate(addr) {
+            MbcAddr::Rom0(_) | MbcAddr::RomX(_) => match addr {
                 0x0000..=0x1FFF => {
-                    // it only changes on these 2 values, anything else does nothing
-                    if value == 0x00 {
-                        self.ram_enabled = false;
-                    } else if value == 0x0A {
-                        self.ram_enabled = true;
-                    }
+                    self.ram_enabled = value & 0x0F == 0x0A;
                 }
                 0x2000..=0x3FFF => {
-                    let len = self.rom.len() as u8;
-                    let mut bank = if value == 0 { 1 } else { value };
-                    let mut mask = 0b0000_1111;
-
-                    while bank > len {
-                        bank &= mask;
-                        mask >>= 1;
-                    }
-
-                    self.rom_bank = (self.rom_bank & 0x60) | bank;
+                    let bank = value & 0x1F;
+                    self.bank1 = if bank == 0 { 1 } else { bank };
                 }
                 0x4000..=0x5FFF => {
-                    let rom_len = self.rom.len();
-                    let ram_len = self.ram.len();
-
-                    // at least 1MiB of rom (0x4000 = 16384 -> 16384 * 64 = 1048576)
-                    if rom_len >= 64 {
-                        // sets bits 5 and 6 of the rom bank number to `value`
-                        let upper_bits = self.rom_bank & 0x60;
-                        let clean = self.rom_bank & !upper_bits;
-                        self.rom_bank = clean | (value << 5);
-                    }
-
-                    // 32KiB of ram (0x2000 = 8192 -> 8192 * 4 = 32768)
-                    // 4 ram banks is the max for MBC1
-                    if ram_len == 4 && self.ram_banking {
-                        // set the ram bank number to the 2 lowest bits of `value`
-                        self.ram_bank = value & 0x3;
-                    }
+                    self.bank2 = value & 0x03;
                 }
                 0x6000..=0x7FFF => {
-                    if value == 0x00 {
-                        self.ram_banking = false;
-                        self.ram_bank = 0;
-                    } else if value == 0x01 {
-                        self.ram_banking = true;
-                    }
+                    self.mode = value & 0x01 == 0x01;
                 }
                 _ => unreachable!(),
             },
             MbcAddr::Ram(a) => {
                 if self.ram_enabled {
-                    self.ram[self.ram_bank as usize][a as usize] = Some(value);
+                    let bank = self.ram_bank() & self.ram.len().saturating_sub(1);
+                    if let Some(bank) = self.ram.get_mut(bank) {
+                        bank[a as usize] = Some(value);
+                    }
                 }
             }
         }
     }
 
-    fn load_rom(&mut self, data: &[u8]) {
-        let mut bank = 0;
-        let mut i = 0;
-        let len = data.len();
-
-        while i < len {
-            // panic if the bank number is larger than the amount of banks in this mbc
-            if bank >= self.rom.len() {
-                panic!("ROM is of insufficient size as configured");
-            }
-
-            // the number of bytes to move into the current bank
-            let offset = if len - i >= 0x4000 { 0x4000 } else { len - i };
+    fn try_load_rom(&mut self, data: &[u8]) -> Result<(), RomLoadError> {
+        load_banks(&mut self.rom, data)?;
+        self.multicart = self.detect_multicart();
 
-            // move all bytes that belong in this bank into this bank
-            for e in i..i + offset {
-                self.rom[bank][e - i] = Some(data[e]);
-            }
-
-            // move onto the next bank, and add `offset` to `i` cause we're past that now
-            bank += 1;
-            i += offset;
-        }
+        Ok(())
     }
 
     fn translate(&self, addr: u16) -> MbcAddr {
@@ -124,4 +143,68 @@ impl Mbc for Mbc1 {
             _ => panic!("Invalid memory translation: ${addr:#06x}"),
         }
     }
+
+    fn rom_bank(&self) -> usize {
+        self.romx_bank()
+    }
+
+    fn load_rom_bank(&self, bank: usize, addr: u16) -> Option<u8> {
+        load_rom_bank(&self.rom, bank, addr)
+    }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        dump_banks(&self.ram)
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) -> Result<(), SaveError> {
+        restore_banks(&mut self.ram, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::mbc::{
+        header::{LOGO, NINTENDO_LOGO},
+        init_mbc, MbcSelector, RamSize, RomSize,
+    };
+
+    #[test]
+    fn mode_1_lower_area() {
+        let mut mbc = init_mbc(MbcSelector::Mbc1(RomSize::Six, RamSize::Zero, false));
+        let mut rom = vec![0; 0x4000 * 128];
+        rom[0x4000 * 0x20] = 0x45;
+        rom[0x4000 * 0x21] = 0x46;
+        mbc.load_rom(&rom);
+
+        mbc.set(0x4000, 0x01);
+        assert_eq!(mbc.load(0x0000), Some(0x00));
+        assert_eq!(mbc.load(0x4000), Some(0x46));
+
+        mbc.set(0x6000, 0x01);
+        assert_eq!(mbc.load(0x0000), Some(0x45));
+    }
+
+    #[test]
+    fn multicart() {
+        let mut mbc = init_mbc(MbcSelector::Mbc1(RomSize::Five, RamSize::Zero, false));
+        let mut rom = vec![0; 0x4000 * 64];
+        for game in 0..4 {
+            let start = 0x40000 * game + LOGO;
+            rom[start..start + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        }
+        rom[0x4000 * 0x12] = 0x45;
+        mbc.load_rom(&rom);
+
+        // bank 2 lands on bits 4-5, and bit 4 of bank 1 is ignored
+        mbc.set(0x2000, 0x12);
+        mbc.set(0x4000, 0x01);
+        assert_eq!(mbc.load(0x4000), Some(0x45));
+
+        mbc.set(0x6000, 0x01);
+        assert_eq!(mbc.load(LOGO as u16), Some(NINTENDO_LOGO[0]));
+    }
 }
@@ -0,0 +1,379 @@
+use super::{load_banks, load_rom_bank, Mbc, MbcAddr, RomLoadError, SaveError};
+
+/// Accelerometer reading when level
+const TILT_CENTER: f32 = 0x81D0 as f32;
+/// Change in the accelerometer reading per 1g of tilt
+const TILT_SCALE: f32 = 0x70 as f32;
+
+/// Chip select bit of the EEPROM register
+const EEPROM_CS: u8 = 0b1000_0000;
+/// Clock bit of the EEPROM register
+const EEPROM_CLK: u8 = 0b0100_0000;
+/// Data in bit of the EEPROM register
+const EEPROM_DI: u8 = 0b0000_0010;
+/// Data out bit of the EEPROM register
+const EEPROM_DO: u8 = 0b0000_0001;
+
+/// Number of 16 bit words in the 93LC56
+const EEPROM_WORDS: usize = 0x80;
+
+#[derive(Clone)]
+pub struct Mbc7 {
+    /// Cartridge ROM, up to 128 banks, each 16384 bytes
+    pub rom: Box<[Box<[Option<u8>; 0x4000]>]>,
+    pub rom_bank: u8,
+    /// Set by writing 0x0A to 0000-1FFF
+    pub ram_enabled: bool,
+    /// Set by writing 0x40 to 4000-5FFF, both enables are needed to access A000-AFFF
+    pub ram_enabled_2: bool,
+    /// Latched accelerometer X reading
+    pub x: u16,
+    /// Latched accelerometer Y reading
+    pub y: u16,
+    /// Set by erasing the latched readings, needed before they can be latched again
+    pub latch_ready: bool,
+    /// Tilt set by the host in g, positive values are right and down
+    pub tilt: (f32, f32),
+    pub eeprom: Eeprom,
+}
+
+/// The 93LC56 serial EEPROM, organized as 128 16 bit words
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Eeprom {
+    pub words: Box<[u16; EEPROM_WORDS]>,
+    pub state: EepromState,
+    /// Whether the write and erase commands are allowed
+    pub write_enabled: bool,
+    pub cs: bool,
+    pub clk: bool,
+    pub data_in: bool,
+    pub data_out: bool,
+}
+
+/// Progress through a serial command, advanced on each rising clock edge
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EepromState {
+    /// Waiting for a start bit
+    Idle,
+    /// Shifting in the 2 bit opcode and 8 bit address, `count` bits so far
+    Command { bits: u16, count: u8 },
+    /// Shifting out a word, `count` bits so far
+    Read { data: u16, count: u8 },
+    /// Shifting in a word to write to `addr`, or every word if `addr` is `None`
+    Write { addr: Option<u8>, data: u16, count: u8 },
+}
+
+impl Default for Eeprom {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Eeprom {
+    pub fn new() -> Self {
+        Self {
+            words: Box::new([0xFFFF; EEPROM_WORDS]),
+            state: EepromState::Idle,
+            write_enabled: false,
+            cs: false,
+            clk: false,
+            data_in: false,
+            data_out: true,
+        }
+    }
+
+    /// Reads the pin register
+    pub fn read(&self) -> u8 {
+        let mut out = 0;
+        if self.cs { out |= EEPROM_CS };
+        if self.clk { out |= EEPROM_CLK };
+        if self.data_in { out |= EEPROM_DI };
+        if self.data_out { out |= EEPROM_DO };
+
+        out
+    }
+
+    /// Writes the pin register, clocking a bit in or out on a rising clock edge
+    pub fn write(&mut self, value: u8) {
+        let rising = !self.clk && value & EEPROM_CLK > 0;
+
+        self.cs = value & EEPROM_CS > 0;
+        self.clk = value & EEPROM_CLK > 0;
+        self.data_in = value & EEPROM_DI > 0;
+
+        if !self.cs {
+            self.state = EepromState::Idle;
+            self.data_out = true;
+        } else if rising {
+            self.clock();
+        }
+    }
+
+    fn clock(&mut self) {
+        let di = self.data_in as u16;
+
+        self.state = match self.state {
+            EepromState::Idle if di == 1 => EepromState::Command { bits: 0, count: 0 },
+            EepromState::Idle => EepromState::Idle,
+            EepromState::Command { bits, count } => {
+                let bits = (bits << 1) | di;
+                if count + 1 < 10 {
+                    EepromState::Command { bits, count: count + 1 }
+                } else {
+                    self.command((bits >> 8) as u8, bits as u8)
+                }
+            }
+            EepromState::Read { data, count } => {
+                self.data_out = data & 0x8000 > 0;
+                if count + 1 < 16 {
+                    EepromState::Read { data: data << 1, count: count + 1 }
+                } else {
+                    EepromState::Idle
+                }
+            }
+            EepromState::Write { addr, data, count } => {
+                let data = (data << 1) | di;
+                if count + 1 < 16 {
+                    EepromState::Write { addr, data, count: count + 1 }
+                } else {
+                    if self.write_enabled {
+                        match addr {
+                            Some(addr) => self.words[addr as usize] = data,
+                            None => self.words.fill(data),
+                        }
+                    }
+
+                    EepromState::Idle
+                }
+            }
+        };
+    }
+
+    /// Runs a complete opcode and address, returning the state to continue in
+    fn command(&mut self, opcode: u8, addr: u8) -> EepromState {
+        let word = addr & (EEPROM_WORDS - 1) as u8;
+
+        match opcode & 0b11 {
+            // READ, a dummy 0 bit comes out before the data
+            0b10 => {
+                self.data_out = false;
+                EepromState::Read { data: self.words[word as usize], count: 0 }
+            }
+            // WRITE
+            0b01 => EepromState::Write { addr: Some(word), data: 0, count: 0 },
+            // ERASE
+            0b11 => {
+                if self.write_enabled {
+                    self.words[word as usize] = 0xFFFF;
+                }
+                EepromState::Idle
+            }
+            // the top 2 address bits pick between the extended commands
+            _ => match addr >> 6 {
+                // EWDS
+                0b00 => {
+                    self.write_enabled = false;
+                    EepromState::Idle
+                }
+                // WRAL
+                0b01 => EepromState::Write { addr: None, data: 0, count: 0 },
+                // ERAL
+                0b10 => {
+                    if self.write_enabled {
+                        self.words.fill(0xFFFF);
+                    }
+                    EepromState::Idle
+                }
+                // EWEN
+                _ => {
+                    self.write_enabled = true;
+                    EepromState::Idle
+                }
+            },
+        }
+    }
+}
+
+impl Mbc for Mbc7 {
+    fn load(&self, addr: u16) -> Option<u8> {
+        let addr = self.translate(addr);
+
+        match addr {
+            MbcAddr::Rom0(a) => self.rom[0][a as usize],
+            MbcAddr::RomX(a) => self.rom[self.rom_bank as usize][a as usize],
+            MbcAddr::Ram(a) => {
+                if !(self.ram_enabled && self.ram_enabled_2) || a >= 0x1000 {
+                    return Some(0xFF);
+                }
+
+                // bits 4-7 of the address select the register
+                let value = match (a >> 4) & 0x0F {
+                    0x2 => self.x as u8,
+                    0x3 => (self.x >> 8) as u8,
+                    0x4 => self.y as u8,
+                    0x5 => (self.y >> 8) as u8,
+                    0x6 => 0x00,
+                    0x8 => self.eeprom.read(),
+                    _ => 0xFF,
+                };
+
+                Some(value)
+            }
+        }
+    }
+
+    fn set(&mut self, addr: u16, value: u8) {
+        match self.translate(addr) {
+            MbcAddr::Rom0(_) | MbcAddr::RomX(_) => match addr {
+                0x0000..=0x1FFF => {
+                    self.ram_enabled = value & 0x0F == 0x0A;
+                }
+                0x2000..=0x3FFF => {
+                    self.rom_bank = value & (self.rom.len() - 1) as u8;
+                }
+                0x4000..=0x5FFF => {
+                    self.ram_enabled_2 = value == 0x40;
+                }
+                0x6000..=0x7FFF => {}
+                _ => unreachable!(),
+            },
+            MbcAddr::Ram(a) => {
+                if !(self.ram_enabled && self.ram_enabled_2) || a >= 0x1000 {
+                    return;
+                }
+
+                match (a >> 4) & 0x0F {
+                    0x0 if value == 0x55 => {
+                        self.x = 0x8000;
+                        self.y = 0x8000;
+                        self.latch_ready = true;
+                    }
+                    0x1 if value == 0xAA && self.latch_ready => {
+                        let (x, y) = self.tilt;
+                        self.x = (TILT_CENTER + x * TILT_SCALE) as u16;
+                        self.y = (TILT_CENTER + y * TILT_SCALE) as u16;
+                        self.latch_ready = false;
+                    }
+                    0x8 => self.eeprom.write(value),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn try_load_rom(&mut self, data: &[u8]) -> Result<(), RomLoadError> {
+        load_banks(&mut self.rom, data)
+    }
+
+    fn translate(&self, addr: u16) -> MbcAddr {
+        match addr {
+            0x0000..=0x3FFF => MbcAddr::Rom0(addr),
+            0x4000..=0x7FFF => MbcAddr::RomX(addr - 0x4000),
+            0xA000..=0xBFFF => MbcAddr::Ram(addr - 0xA000),
+            _ => panic!("Invalid memory translation: ${addr:#06x}"),
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+
+    fn load_rom_bank(&self, bank: usize, addr: u16) -> Option<u8> {
+        load_rom_bank(&self.rom, bank, addr)
+    }
+
+    fn has_battery(&self) -> bool {
+        true
+    }
+
+    /// Dumps the EEPROM as 256 bytes, with each word big endian
+    fn dump_ram(&self) -> Vec<u8> {
+        self.eeprom.words.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) -> Result<(), SaveError> {
+        let expected = EEPROM_WORDS * 2;
+        if data.len() != expected {
+            return Err(SaveError::InvalidSize { expected, found: data.len() });
+        }
+
+        for (word, bytes) in self.eeprom.words.iter_mut().zip(data.chunks(2)) {
+            *word = u16::from_be_bytes([bytes[0], bytes[1]]);
+        }
+
+        Ok(())
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::mbc::{init_mbc, Mbc, MbcSelector, RomSize};
+
+    /// Clocks `count` bits of `bits` into the EEPROM, MSB first
+    fn send(mbc: &mut Box<dyn Mbc>, bits: u32, count: u8) {
+        for i in (0..count).rev() {
+            let di = ((bits >> i) as u8 & 1) << 1;
+            mbc.set(0xA080, 0x80 | di);
+            mbc.set(0xA080, 0xC0 | di);
+        }
+    }
+
+    /// Clocks 16 bits out of the EEPROM
+    fn receive(mbc: &mut Box<dyn Mbc>) -> u16 {
+        let mut out = 0;
+        for _ in 0..16 {
+            mbc.set(0xA080, 0x80);
+            mbc.set(0xA080, 0xC0);
+            out = (out << 1) | (mbc.load(0xA080).unwrap() & 1) as u16;
+        }
+        out
+    }
+
+    fn enabled() -> Box<dyn Mbc> {
+        let mut mbc = init_mbc(MbcSelector::Mbc7(RomSize::Zero));
+        mbc.set(0x0000, 0x0A);
+        mbc.set(0x4000, 0x40);
+        mbc
+    }
+
+    #[test]
+    fn accelerometer() {
+        let mut mbc = enabled();
+        mbc.set_tilt(1.0, -1.0);
+
+        // can't latch without erasing first
+        mbc.set(0xA010, 0xAA);
+        assert_eq!(mbc.load(0xA020), Some(0x00));
+
+        mbc.set(0xA000, 0x55);
+        mbc.set(0xA010, 0xAA);
+        let x = u16::from_le_bytes([mbc.load(0xA020).unwrap(), mbc.load(0xA030).unwrap()]);
+        let y = u16::from_le_bytes([mbc.load(0xA040).unwrap(), mbc.load(0xA050).unwrap()]);
+        assert_eq!((x, y), (0x8240, 0x8160));
+    }
+
+    #[test]
+    fn eeprom_write_read() {
+        let mut mbc = enabled();
+
+        // start bit, opcode, then address
+        // EWEN, then WRITE 0x1234 to word 5
+        send(&mut mbc, 0x4C0, 11);
+        mbc.set(0xA080, 0x00);
+        send(&mut mbc, 0x505, 11);
+        send(&mut mbc, 0x1234, 16);
+        mbc.set(0xA080, 0x00);
+
+        // READ word 5
+        send(&mut mbc, 0x605, 11);
+        assert_eq!(mbc.load(0xA080).unwrap() & 1, 0);
+        assert_eq!(receive(&mut mbc), 0x1234);
+        mbc.set(0xA080, 0x00);
+
+        assert_eq!(&mbc.dump_ram()[10..12], &[0x12, 0x34]);
+    }
+}
@@ -0,0 +1,422 @@
+use super::{
+    dump_banks, load_banks, load_rom_bank, restore_banks, unix_time, Mbc, MbcAddr, RomLoadError, SaveError,
+};
+
+/// Seconds register select value
+const RTC_S: u8 = 0x08;
+/// Minutes register select value
+const RTC_M: u8 = 0x09;
+/// Hours register select value
+const RTC_H: u8 = 0x0A;
+/// Lower 8 bits of the day counter select value
+const RTC_DL: u8 = 0x0B;
+/// Upper bit of the day counter, halt and day carry select value
+const RTC_DH: u8 = 0x0C;
+
+/// Size of the RTC footer appended to save files, with a 64 bit timestamp
+pub const RTC_FOOTER_LEN: usize = 48;
+/// Size of the older RTC footer variant, with a 32 bit timestamp
+const RTC_FOOTER_LEN_SHORT: usize = 44;
+
+#[derive(Clone)]
+pub struct Mbc3 {
+    /// Cartridge ROM, up to 128 banks, each 16384 bytes
+    pub rom: Box<[Box<[Option<u8>; 0x4000]>]>,
+    /// Cartridge RAM, up to 4 banks, each 8192 bytes
+    pub ram: Box<[Box<[Option<u8>; 0x2000]>]>,
+    pub rom_bank: u8,
+    /// Either a RAM bank (0x00-0x03) or an RTC register (0x08-0x0C)
+    pub ram_bank: u8,
+    /// Enables both RAM and the RTC registers
+    pub ram_enabled: bool,
+    /// Set when 0x00 was the last value written to the latch register
+    pub latch_ready: bool,
+    /// Only present on cartridges with a timer
+    pub rtc: Option<Rtc>,
+    pub battery: bool,
+}
+
+/// The MBC3 real-time clock
+///
+/// The counters are kept up to date against the host clock, and only move forward when they are accessed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rtc {
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    /// 9 bit day counter
+    pub days: u16,
+    pub halted: bool,
+    /// Set when the day counter overflows, stays set until cleared by the game
+    pub day_carry: bool,
+    /// Register values as of the last latch, in the order S, M, H, DL, DH
+    pub latched: [u8; 5],
+    /// Unix timestamp the counters were last brought up to date at
+    pub last_update: u64,
+}
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Self {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            day_carry: false,
+            latched: [0; 5],
+            last_update: unix_time(),
+        }
+    }
+
+    /// Advances the counters by the time passed since the last update
+    pub fn update(&mut self) {
+        let now = unix_time();
+
+        if !self.halted {
+            self.advance(now.saturating_sub(self.last_update));
+        }
+
+        self.last_update = now;
+    }
+
+    /// Advances the counters by `secs` seconds, carrying into each larger unit
+    pub fn advance(&mut self, secs: u64) {
+        let seconds = self.seconds as u64 + secs;
+        self.seconds = (seconds % 60) as u8;
+
+        let minutes = self.minutes as u64 + seconds / 60;
+        self.minutes = (minutes % 60) as u8;
+
+        let hours = self.hours as u64 + minutes / 60;
+        self.hours = (hours % 24) as u8;
+
+        let days = self.days as u64 + hours / 24;
+        if days > 0x1FF {
+            self.day_carry = true;
+        }
+        self.days = (days % 0x200) as u16;
+    }
+
+    /// Copies the current counters into the latched registers
+    pub fn latch(&mut self) {
+        self.update();
+        self.latched = [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            self.dh(),
+        ];
+    }
+
+    /// Reads a latched register, where `reg` is the value used to select it
+    pub fn read(&self, reg: u8) -> u8 {
+        match reg {
+            RTC_S..=RTC_DH => self.latched[(reg - RTC_S) as usize],
+            _ => 0xFF,
+        }
+    }
+
+    /// Writes to a live register, where `reg` is the value used to select it
+    pub fn write(&mut self, reg: u8, value: u8) {
+        self.update();
+
+        match reg {
+            RTC_S => self.seconds = value & 0x3F,
+            RTC_M => self.minutes = value & 0x3F,
+            RTC_H => self.hours = value & 0x1F,
+            RTC_DL => self.days = (self.days & 0x100) | value as u16,
+            RTC_DH => {
+                self.days = (self.days & 0xFF) | ((value as u16 & 1) << 8);
+                self.halted = value & 0b0100_0000 > 0;
+                self.day_carry = value & 0b1000_0000 > 0;
+            }
+            _ => {}
+        }
+    }
+
+    /// Serializes the clock into the footer layout shared by most emulators
+    ///
+    /// The live registers and the latched registers are stored as little endian 32 bit values in the order S, M, H, DL, DH,
+    /// followed by the Unix timestamp of the last update as a little endian 64 bit value
+    pub fn to_footer(&self) -> [u8; RTC_FOOTER_LEN] {
+        let live = [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            self.dh(),
+        ];
+
+        let mut out = [0; RTC_FOOTER_LEN];
+        let regs = live.iter().chain(self.latched.iter());
+
+        for (chunk, reg) in out.chunks_mut(4).zip(regs) {
+            chunk.copy_from_slice(&(*reg as u32).to_le_bytes());
+        }
+
+        out[40..48].copy_from_slice(&self.last_update.to_le_bytes());
+        out
+    }
+
+    /// Restores the clock from a footer produced by `Rtc::to_footer()`, then catches it up to the current time
+    ///
+    /// Accepts both the 48 byte footer and the older 44 byte one with a 32 bit timestamp
+    pub fn from_footer(&mut self, footer: &[u8]) -> Result<(), SaveError> {
+        let last_update = match footer.len() {
+            RTC_FOOTER_LEN => u64::from_le_bytes(footer[40..48].try_into().unwrap()),
+            RTC_FOOTER_LEN_SHORT => u32::from_le_bytes(footer[40..44].try_into().unwrap()) as u64,
+            found => {
+                return Err(SaveError::InvalidSize {
+                    expected: RTC_FOOTER_LEN,
+                    found,
+                })
+            }
+        };
+
+        let mut regs = [0; 10];
+        for (reg, chunk) in regs.iter_mut().zip(footer.chunks(4)) {
+            *reg = chunk[0];
+        }
+
+        self.seconds = regs[0] & 0x3F;
+        self.minutes = regs[1] & 0x3F;
+        self.hours = regs[2] & 0x1F;
+        self.days = regs[3] as u16 | ((regs[4] as u16 & 1) << 8);
+        self.halted = regs[4] & 0b0100_0000 > 0;
+        self.day_carry = regs[4] & 0b1000_0000 > 0;
+        self.latched.copy_from_slice(&regs[5..10]);
+        self.last_update = last_update;
+        self.update();
+
+        Ok(())
+    }
+
+    /// Combines the upper day bit, halt flag and day carry into the DH register layout
+    fn dh(&self) -> u8 {
+        let mut out = (self.days >> 8) as u8 & 1;
+
+        if self.halted {
+            out |= 1 << 6;
+        }
+        if self.day_carry {
+            out |= 1 << 7;
+        }
+
+        out
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn load(&self, addr: u16) -> Option<u8> {
+        let addr = self.translate(addr);
+
+        match addr {
+            MbcAddr::Rom0(a) => self.rom[0][a as usize],
+            MbcAddr::RomX(a) => self.rom[self.rom_bank as usize][a as usize],
+            MbcAddr::Ram(a) => {
+                if !self.ram_enabled {
+                    return Some(0xFF);
+                }
+
+                match (self.ram_bank, &self.rtc) {
+                    (0x00..=0x03, _) => self
+                        .ram
+                        .get(self.ram_bank as usize)
+                        .map_or(Some(0xFF), |bank| bank[a as usize]),
+                    (RTC_S..=RTC_DH, Some(rtc)) => Some(rtc.read(self.ram_bank)),
+                    _ => Some(0xFF),
+                }
+            }
+        }
+    }
+
+    fn set(&mut self, addr: u16, value: u8) {
+        match self.translate(addr) {
+            MbcAddr::Rom0(_) | MbcAddr::RomX(_) => match addr {
+                0x0000..=0x1FFF => {
+                    self.ram_enabled = value & 0x0F == 0x0A;
+                }
+                0x2000..=0x3FFF => {
+                    let bank = value & 0x7F;
+                    let bank = if bank == 0 { 1 } else { bank };
+
+                    // rom sizes are always powers of 2, so this wraps around to the available banks
+                    self.rom_bank = bank & (self.rom.len() - 1) as u8;
+                }
+                0x4000..=0x5FFF => {
+                    self.ram_bank = value;
+                }
+                0x6000..=0x7FFF => {
+                    // the latch happens on the rising edge of a 0x00 -> 0x01 write sequence
+                    if self.latch_ready && value == 0x01 {
+                        if let Some(rtc) = self.rtc.as_mut() {
+                            rtc.latch();
+                        }
+                    }
+
+                    self.latch_ready = value == 0x00;
+                }
+                _ => unreachable!(),
+            },
+            MbcAddr::Ram(a) => {
+                if !self.ram_enabled {
+                    return;
+                }
+
+                match (self.ram_bank, self.rtc.as_mut()) {
+                    (0x00..=0x03, _) => {
+                        if let Some(bank) = self.ram.get_mut(self.ram_bank as usize) {
+                            bank[a as usize] = Some(value);
+                        }
+                    }
+                    (RTC_S..=RTC_DH, Some(rtc)) => rtc.write(self.ram_bank, value),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn try_load_rom(&mut self, data: &[u8]) -> Result<(), RomLoadError> {
+        load_banks(&mut self.rom, data)
+    }
+
+    fn translate(&self, addr: u16) -> MbcAddr {
+        match addr {
+            0x0000..=0x3FFF => MbcAddr::Rom0(addr),
+            0x4000..=0x7FFF => MbcAddr::RomX(addr - 0x4000),
+            0xA000..=0xBFFF => MbcAddr::Ram(addr - 0xA000),
+            _ => panic!("Invalid memory translation: ${addr:#06x}"),
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+
+    fn load_rom_bank(&self, bank: usize, addr: u16) -> Option<u8> {
+        load_rom_bank(&self.rom, bank, addr)
+    }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        let mut out = dump_banks(&self.ram);
+
+        if let Some(rtc) = &self.rtc {
+            out.extend_from_slice(&rtc.to_footer());
+        }
+
+        out
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) -> Result<(), SaveError> {
+        let ram_len = self.ram.len() * 0x2000;
+
+        match self.rtc.as_mut() {
+            // saves from emulators without rtc support won't have a footer, in which case the clock is left alone
+            Some(rtc) if data.len() > ram_len => {
+                rtc.from_footer(&data[ram_len..])?;
+                restore_banks(&mut self.ram, &data[..ram_len])
+            }
+            _ => restore_banks(&mut self.ram, data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::mbc::{init_mbc, Mbc3Features, MbcSelector, RamSize, RomSize};
+
+    use super::Rtc;
+
+    #[test]
+    fn rtc_rollover() {
+        let mut rtc = Rtc::new();
+        rtc.seconds = 59;
+        rtc.minutes = 59;
+        rtc.hours = 23;
+        rtc.days = 0x1FF;
+
+        rtc.advance(1);
+
+        assert_eq!((rtc.seconds, rtc.minutes, rtc.hours, rtc.days), (0, 0, 0, 0));
+        assert!(rtc.day_carry);
+    }
+
+    #[test]
+    fn rtc_latch() {
+        let mut mbc = init_mbc(MbcSelector::Mbc3(RomSize::Zero, RamSize::Two, Mbc3Features { timer: true, battery: false }));
+
+        // enable ram and rtc, select the minutes register
+        mbc.set(0x0000, 0x0A);
+        mbc.set(0x4000, 0x09);
+        mbc.set(0xA000, 42);
+
+        // the write goes to the live register, the latched copy is unchanged until latched
+        assert_eq!(mbc.load(0xA000), Some(0));
+
+        mbc.set(0x6000, 0x00);
+        mbc.set(0x6000, 0x01);
+        assert_eq!(mbc.load(0xA000), Some(42));
+    }
+
+    #[test]
+    fn rtc_halt() {
+        let mut rtc = Rtc::new();
+        rtc.write(0x0C, 0b0100_0000);
+        rtc.last_update -= 100;
+        rtc.update();
+
+        assert_eq!(rtc.seconds, 0);
+        assert!(rtc.halted);
+    }
+
+    #[test]
+    fn rtc_footer() {
+        let mut mbc = init_mbc(MbcSelector::Mbc3(RomSize::Zero, RamSize::Two, Mbc3Features { timer: true, battery: true }));
+        mbc.set(0x0000, 0x0A);
+        mbc.set(0xA000, 0x45);
+        mbc.set(0x4000, 0x0A);
+        mbc.set(0xA000, 5);
+        // halt the clock so it can't tick between saving and loading
+        mbc.set(0x4000, 0x0C);
+        mbc.set(0xA000, 0b0100_0000);
+
+        let save = mbc.dump_ram();
+        assert_eq!(save.len(), 0x2000 + 48);
+        assert_eq!(save[0], 0x45);
+        assert_eq!(save[0x2000 + 8], 5);
+
+        let mut other = init_mbc(MbcSelector::Mbc3(RomSize::Zero, RamSize::Two, Mbc3Features { timer: true, battery: true }));
+        other.restore_ram(&save).unwrap();
+        assert_eq!(other.dump_ram()[..0x2000 + 40], save[..0x2000 + 40]);
+    }
+
+    #[test]
+    fn ram_banks() {
+        let mut mbc = init_mbc(MbcSelector::Mbc3(RomSize::Zero, RamSize::Three, Mbc3Features::default()));
+        mbc.set(0x0000, 0x0A);
+
+        mbc.set(0x4000, 0x01);
+        mbc.set(0xA123, 0x45);
+        mbc.set(0x4000, 0x02);
+        assert_eq!(mbc.load(0xA123), None);
+
+        mbc.set(0x4000, 0x01);
+        assert_eq!(mbc.load(0xA123), Some(0x45));
+
+        // rtc registers are open bus without a timer
+        mbc.set(0x4000, 0x08);
+        assert_eq!(mbc.load(0xA123), Some(0xFF));
+    }
+}
@@ -0,0 +1,133 @@
+use std::slice;
+
+use super::{dump_banks, load_banks, load_rom_bank, restore_banks, Mbc, MbcAddr, RomLoadError, SaveError};
+
+#[derive(Clone)]
+pub struct Mbc2 {
+    /// Cartridge ROM, up to 16 banks, each 16384 bytes
+    pub rom: Box<[Box<[Option<u8>; 0x4000]>]>,
+    /// Built-in RAM, 512 half-bytes. Only the lower nibble of each cell is used
+    pub ram: Box<[Option<u8>; 0x200]>,
+    pub rom_bank: u8,
+    pub ram_enabled: bool,
+    pub battery: bool,
+}
+
+impl Mbc for Mbc2 {
+    fn load(&self, addr: u16) -> Option<u8> {
+        let addr = self.translate(addr);
+
+        match addr {
+            MbcAddr::Rom0(a) => self.rom[0][a as usize],
+            MbcAddr::RomX(a) => self.rom[self.rom_bank as usize][a as usize],
+            MbcAddr::Ram(a) => {
+                if self.ram_enabled {
+                    // only 4 bits are wired up, the upper nibble floats high
+                    self.ram[a as usize].map(|value| value | 0xF0)
+                } else {
+                    Some(0xFF)
+                }
+            }
+        }
+    }
+
+    fn set(&mut self, addr: u16, value: u8) {
+        match self.translate(addr) {
+            MbcAddr::Rom0(_) => {
+                // bit 8 of the address selects the register
+                if addr & 0x100 == 0 {
+                    self.ram_enabled = value & 0x0F == 0x0A;
+                } else {
+                    let bank = value & 0x0F;
+                    let bank = if bank == 0 { 1 } else { bank };
+
+                    self.rom_bank = bank & (self.rom.len() - 1) as u8;
+                }
+            }
+            MbcAddr::RomX(_) => {}
+            MbcAddr::Ram(a) => {
+                if self.ram_enabled {
+                    self.ram[a as usize] = Some(value & 0x0F);
+                }
+            }
+        }
+    }
+
+    fn try_load_rom(&mut self, data: &[u8]) -> Result<(), RomLoadError> {
+        load_banks(&mut self.rom, data)
+    }
+
+    fn translate(&self, addr: u16) -> MbcAddr {
+        match addr {
+            0x0000..=0x3FFF => MbcAddr::Rom0(addr),
+            0x4000..=0x7FFF => MbcAddr::RomX(addr - 0x4000),
+            // the 512 cells are echoed across the whole external ram area
+            0xA000..=0xBFFF => MbcAddr::Ram((addr - 0xA000) & 0x1FF),
+            _ => panic!("Invalid memory translation: ${addr:#06x}"),
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank as usize
+    }
+
+    fn load_rom_bank(&self, bank: usize, addr: u16) -> Option<u8> {
+        load_rom_bank(&self.rom, bank, addr)
+    }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    /// Dumps the built-in RAM as 512 bytes, one per half-byte cell
+    fn dump_ram(&self) -> Vec<u8> {
+        dump_banks(slice::from_ref(&self.ram))
+    }
+
+    fn restore_ram(&mut self, data: &[u8]) -> Result<(), SaveError> {
+        restore_banks(slice::from_mut(&mut self.ram), data)?;
+
+        for cell in self.ram.iter_mut().flatten() {
+            *cell &= 0x0F;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::mbc::{init_mbc, MbcSelector, RomSize};
+
+    #[test]
+    fn register_select() {
+        let mut mbc = init_mbc(MbcSelector::Mbc2(RomSize::Three, false));
+        let mut rom = vec![0; 0x4000 * 16];
+        rom[0x4000 * 5] = 0x45;
+        mbc.load_rom(&rom);
+
+        // bit 8 clear, this is the ram enable register so the bank doesn't change
+        mbc.set(0x0000, 0x05);
+        assert_eq!(mbc.load(0x4000), Some(0x00));
+
+        mbc.set(0x0100, 0x05);
+        assert_eq!(mbc.load(0x4000), Some(0x45));
+    }
+
+    #[test]
+    fn half_byte_ram() {
+        let mut mbc = init_mbc(MbcSelector::Mbc2(RomSize::Zero, true));
+        mbc.set(0x0000, 0x0A);
+
+        mbc.set(0xA010, 0x35);
+        assert_eq!(mbc.load(0xA010), Some(0xF5));
+
+        // echoed every 512 bytes
+        assert_eq!(mbc.load(0xA210), Some(0xF5));
+        assert_eq!(mbc.load(0xBE10), Some(0xF5));
+
+        let save = mbc.dump_ram();
+        assert_eq!(save.len(), 0x200);
+        assert_eq!(save[0x10], 0x05);
+    }
+}
@@ -0,0 +1,46 @@
+/// The hardware revision being emulated
+///
+/// Picks the state the boot ROM leaves the system in, and gates behaviour that differs between revisions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Model {
+    /// Original Game Boy with the early boot ROM
+    Dmg0,
+    /// Original Game Boy
+    #[default]
+    Dmg,
+    /// Game Boy Pocket and Light
+    Mgb,
+    /// Super Game Boy
+    Sgb,
+    /// Super Game Boy 2
+    Sgb2,
+    /// Game Boy Color
+    Cgb,
+    /// Game Boy Advance, running in Game Boy Color mode
+    Agb,
+}
+
+impl Model {
+    /// Returns whether the model has the Game Boy Color hardware, such as WRAM and VRAM banking
+    pub fn is_cgb(&self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+
+    /// Returns whether the model is running inside a Super Game Boy
+    pub fn is_sgb(&self) -> bool {
+        matches!(self, Model::Sgb | Model::Sgb2)
+    }
+
+    /// The internal DIV counter once the boot ROM hands over to the cartridge, DIV reads the upper byte
+    ///
+    /// The SGB and CGB boot ROMs take longer or shorter depending on the cartridge header, so their values are for a
+    /// header with a valid logo and no title matches
+    pub fn post_boot_div(&self) -> u16 {
+        match self {
+            Model::Dmg0 => 0x182C,
+            Model::Dmg | Model::Mgb => 0xABCC,
+            Model::Sgb | Model::Sgb2 => 0xD85C,
+            Model::Cgb | Model::Agb => 0x1EA0,
+        }
+    }
+}
@@ -4,7 +4,7 @@ use objects::Object;
 use palettes::{Color, ObjPalettes, Palette};
 use regs::{Lcdc, Stat};
 
-use crate::{memory::{self, Memory, OAM, OAM_END, SCX, SCY, WX, WY}, Mmu};
+use crate::{memory::{self, Memory, OAM, OAM_END, SCX, SCY, WX, WY}, Mmu, Model};
 
 pub mod regs;
 pub mod palettes;
@@ -148,10 +148,11 @@ pub struct Ppu {
     pub status: PpuStatus,
     pub enabled: bool,
     pub draw_ready: bool,
+    pub model: Model,
 }
 
 impl Ppu {
-    pub fn new() -> Self {
+    pub fn new(model: Model) -> Self {
         let lcdc = 0x91.into();
         let stat = Stat::new();
         let coords = PpuCoords { x: 0, y: 0 };
@@ -176,9 +177,19 @@ impl Ppu {
             status,
             enabled,
             draw_ready,
+            model,
         }
     }
     
+    /// Creates a PPU in its power on state, with the LCD off, LY at 0 and in mode 0
+    pub fn power_on(model: Model) -> Self {
+        let mut ppu = Self::new(model);
+        ppu.lcdc = 0.into();
+        ppu.palette.update(0);
+
+        ppu
+    }
+
     pub fn tick<T: Memory>(&mut self, memory: &mut T) {
         if !self.lcdc.lcd_enable { return };
 
@@ -477,8 +488,21 @@ impl Ppu {
         self.lcdc = lcdc.into();
     }
 
-    pub fn set_stat(&mut self, stat: u8) {
-        self.stat = stat.into();
+    /// Sets the writable bits of STAT, leaving the mode and LYC match as they are
+    ///
+    /// ### Return Variants
+    /// - `true` if the write raises a spurious STAT interrupt, which DMG models do when written with the LCD on
+    ///   during HBlank, VBlank, or while LY matches LYC
+    /// - `false` otherwise
+    pub fn set_stat(&mut self, stat: u8) -> bool {
+        let mut new: Stat = stat.into();
+        new.mode = self.stat.mode;
+        new.lyc_match = self.stat.lyc_match;
+        new.int = false;
+        self.stat = new;
+
+        let blanking = matches!(self.stat.mode, PpuMode::Mode0 | PpuMode::Mode1);
+        !self.model.is_cgb() && self.lcdc.lcd_enable && (blanking || self.stat.lyc_match)
     }
 
     pub fn set_palette(&mut self, bgp: u8) {